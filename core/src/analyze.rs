@@ -1,8 +1,13 @@
 use std::collections::HashSet;
 
+use itertools::Itertools;
+use quote::ToTokens;
 use syn::spanned::Spanned;
 
-use crate::parse::ParsedConditionalQueryAs;
+use crate::{
+    parse::{CountOption, ParsedConditionalQueryAs},
+    suggest, DatabaseType,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum AnalyzeError {
@@ -25,6 +30,16 @@ pub enum AnalyzeError {
         root_ident: proc_macro2::Ident,
         path: String,
     },
+    #[error("expected match arm label of the form `#[label = \"...\"]`")]
+    InvalidArmLabel(proc_macro2::Span),
+    #[error("`count` omits unknown compile-time binding: {name}")]
+    UnknownOmittedBinding {
+        name: proc_macro2::Ident,
+        /// The most similarly named compile-time binding, if any.
+        suggestion: Option<String>,
+    },
+    #[error("expected `postgres`, `mysql`, `sqlite` or `_` as dialect")]
+    InvalidDialect(proc_macro2::Span),
 }
 
 /// This represents the finished second step in the processing pipeline.
@@ -35,6 +50,8 @@ pub(crate) struct AnalyzedConditionalQueryAs {
     pub(crate) output_type: syn::Ident,
     pub(crate) query_string: syn::LitStr,
     pub(crate) compile_time_bindings: Vec<CompileTimeBinding>,
+    pub(crate) dialect_bindings: Vec<DialectBinding>,
+    pub(crate) count: Option<CountOption>,
 }
 
 /// This represents a single combination of a single compiletime binding of a query.
@@ -45,16 +62,85 @@ pub(crate) struct CompileTimeBinding {
     pub(crate) expression: syn::Expr,
     /// Each entry in this Vec represents a single expanded `match` and the
     /// binding names with the binding values from that specific arm.
-    /// (`match arm pattern`, Vec(binding_name, binding_value)`
-    pub(crate) arms: Vec<(syn::Pat, Vec<(syn::Ident, syn::LitStr)>)>,
+    pub(crate) arms: Vec<CompileTimeBindingArm>,
+}
+
+/// This represents a single arm of a compile-time binding's `match` statement.
+#[derive(Clone, Debug)]
+pub(crate) struct CompileTimeBindingArm {
+    /// The pattern of the match arm.
+    pub(crate) pattern: syn::Pat,
+    /// The label of the match arm.
+    ///
+    /// This is taken from a `#[label = "..."]` attribute on the arm if there is one, and is
+    /// otherwise the pattern itself with all whitespace removed.
+    pub(crate) label: String,
+    /// The binding names along with the binding values from this arm.
+    pub(crate) values: Vec<(syn::Ident, syn::LitStr)>,
+}
+
+/// This represents a `dialect { ... }` binding, which is resolved for the database the query is
+/// expanded for instead of being matched at run time.
+#[derive(Debug)]
+pub(crate) struct DialectBinding {
+    /// The binding names, for reporting bindings without an arm for a database.
+    pub(crate) names: String,
+    pub(crate) span: proc_macro2::Span,
+    pub(crate) arms: Vec<DialectArm>,
+}
+
+/// This represents a single arm of a dialect binding, e.g. `postgres | mysql => "NOW()"`.
+#[derive(Debug)]
+pub(crate) struct DialectArm {
+    /// The databases this arm applies to, or `None` for the `_` wildcard.
+    pub(crate) databases: Option<Vec<DatabaseType>>,
+    /// The binding names along with the binding values from this arm.
+    pub(crate) values: Vec<(syn::Ident, syn::LitStr)>,
+}
+
+impl AnalyzedConditionalQueryAs {
+    /// Returns the binding names along with the binding values of all arms, those of compile-time
+    /// bindings before those of dialect bindings.
+    pub(crate) fn binding_values(&self) -> impl Iterator<Item = &(syn::Ident, syn::LitStr)> {
+        self.compile_time_bindings
+            .iter()
+            .flat_map(|binding| &binding.arms)
+            .flat_map(|arm| &arm.values)
+            .chain(
+                self.dialect_bindings
+                    .iter()
+                    .flat_map(|binding| &binding.arms)
+                    .flat_map(|arm| &arm.values),
+            )
+    }
+}
+
+impl DialectBinding {
+    /// Returns the values of the first arm applying to `database_type`.
+    pub(crate) fn resolve(
+        &self,
+        database_type: DatabaseType,
+    ) -> Option<&[(syn::Ident, syn::LitStr)]> {
+        self.arms
+            .iter()
+            .find(|arm| {
+                arm.databases
+                    .as_ref()
+                    .is_none_or(|databases| databases.contains(&database_type))
+            })
+            .map(|arm| arm.values.as_slice())
+    }
 }
 
 /// Further parse and analyze all compiletime binding statements.
 /// Each binding is split into individual entries of this form:
 /// (`match arm pattern`, Vec(binding_name, binding_value)`
+///
+/// Errors don't stop the analysis, so that all of them can be reported at once.
 pub(crate) fn analyze(
     parsed: ParsedConditionalQueryAs,
-) -> Result<AnalyzedConditionalQueryAs, AnalyzeError> {
+) -> Result<AnalyzedConditionalQueryAs, Vec<AnalyzeError>> {
+    let mut errors = Vec::new();
     let mut compile_time_bindings = Vec::new();
 
     let mut known_binding_names = HashSet::new();
@@ -66,66 +152,41 @@ pub(crate) fn analyze(
         let binding_names: Vec<_> = names.into_iter().collect();
 
         // Find duplicate compile-time bindings.
-        for name in &binding_names {
-            let Some(first) = known_binding_names.get(name) else {
-                known_binding_names.insert(name.clone());
-                continue;
-            };
-            return Err(AnalyzeError::DuplicatedCompileTimeBindingsFound {
-                first: first.clone(),
-                second: name.clone(),
-            });
-        }
+        insert_binding_names(&mut known_binding_names, &binding_names, &mut errors);
 
         let mut bindings = Vec::new();
         for arm in match_expr.arms {
             let arm_span = arm.body.span();
+            let label = arm_label(&arm).unwrap_or_else(|err| {
+                errors.push(err);
+                String::new()
+            });
 
-            let binding_values = match *arm.body {
-                // If the match arm expression just contains a literal, use that.
-                syn::Expr::Lit(syn::ExprLit {
-                    lit: syn::Lit::Str(literal),
-                    ..
-                }) => vec![literal],
-
-                // If there's a tuple, treat each literal inside that tuple as a binding value.
-                syn::Expr::Tuple(tuple) => {
-                    let mut values = Vec::new();
-                    for elem in tuple.elems {
-                        match elem {
-                            syn::Expr::Lit(syn::ExprLit {
-                                lit: syn::Lit::Str(literal),
-                                ..
-                            }) => values.push(literal),
-
-                            _ => return Err(AnalyzeError::ExpectedStringLiteral(elem.span())),
-                        }
-                    }
-                    values
+            let binding_values = match binding_values(*arm.body).and_then(|binding_values| {
+                check_value_count(
+                    &binding_names,
+                    binding_names_span,
+                    &binding_values,
+                    arm_span,
+                )
+                .map(|()| binding_values)
+            }) {
+                Ok(binding_values) => binding_values,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
                 }
-
-                body => return Err(AnalyzeError::ExpectedStringLiteral(body.span())),
             };
 
-            // There must always be a matching amount of binding values in each match arm.
-            // Error if there are more or fewer values than binding names.
-            if binding_names.len() != binding_values.len() {
-                return Err(AnalyzeError::BindingNameValueLengthMismatch {
-                    names: binding_names.len(),
-                    names_span: binding_names_span,
-                    values: binding_values.len(),
-                    values_span: arm_span,
-                });
-            }
-
-            bindings.push((
-                arm.pat,
-                binding_names
+            bindings.push(CompileTimeBindingArm {
+                pattern: arm.pat,
+                label,
+                values: binding_names
                     .iter()
                     .cloned()
                     .zip(binding_values)
                     .collect::<Vec<_>>(),
-            ));
+            });
         }
 
         compile_time_bindings.push(CompileTimeBinding {
@@ -134,39 +195,245 @@ pub(crate) fn analyze(
         });
     }
 
-    compile_time_bindings::validate_compile_time_bindings(&compile_time_bindings)?;
+    let mut dialect_bindings = Vec::new();
+    for (names, dialect_binding) in parsed.dialect_bindings {
+        let binding_names_span = names.span();
+        let binding_names: Vec<_> = names.into_iter().collect();
+        insert_binding_names(&mut known_binding_names, &binding_names, &mut errors);
+
+        let mut arms = Vec::new();
+        for arm in dialect_binding.arms {
+            let arm_span = arm.body.span();
+            let databases = dialect_databases(&arm.pat);
+            let binding_values = binding_values(*arm.body).and_then(|binding_values| {
+                check_value_count(
+                    &binding_names,
+                    binding_names_span,
+                    &binding_values,
+                    arm_span,
+                )
+                .map(|()| binding_values)
+            });
+            let (databases, binding_values) = match (databases, binding_values) {
+                (Ok(databases), Ok(binding_values)) => (databases, binding_values),
+                (databases, binding_values) => {
+                    errors.extend(databases.err());
+                    errors.extend(binding_values.err());
+                    continue;
+                }
+            };
+
+            arms.push(DialectArm {
+                databases,
+                values: binding_names.iter().cloned().zip(binding_values).collect(),
+            });
+        }
+
+        dialect_bindings.push(DialectBinding {
+            names: binding_names.iter().map(ToString::to_string).join(", "),
+            span: dialect_binding.keyword.span,
+            arms,
+        });
+    }
 
-    Ok(AnalyzedConditionalQueryAs {
+    if let Some(count) = &parsed.count {
+        let known_names: Vec<_> = known_binding_names
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        for name in &count.omit {
+            if known_binding_names.contains(name) {
+                continue;
+            }
+            let suggestion =
+                suggest::closest_match(&name.to_string(), known_names.iter().map(String::as_str))
+                    .map(ToString::to_string);
+            errors.push(AnalyzeError::UnknownOmittedBinding {
+                name: name.clone(),
+                suggestion,
+            });
+        }
+    }
+
+    let analyzed = AnalyzedConditionalQueryAs {
         output_type: parsed.output_type,
         query_string: parsed.query_string,
         compile_time_bindings,
-    })
+        dialect_bindings,
+        count: parsed.count,
+    };
+
+    if let Err(err) =
+        compile_time_bindings::validate_compile_time_bindings(analyzed.binding_values())
+    {
+        errors.push(err);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(analyzed)
+}
+
+/// Adds `names` to `known_binding_names`, reporting every name which is already known.
+fn insert_binding_names(
+    known_binding_names: &mut HashSet<syn::Ident>,
+    names: &[syn::Ident],
+    errors: &mut Vec<AnalyzeError>,
+) {
+    for name in names {
+        let Some(first) = known_binding_names.get(name) else {
+            known_binding_names.insert(name.clone());
+            continue;
+        };
+        errors.push(AnalyzeError::DuplicatedCompileTimeBindingsFound {
+            first: first.clone(),
+            second: name.clone(),
+        });
+    }
 }
 
-mod compile_time_bindings {
+/// Returns the binding values of an arm, which is either a string literal or a tuple of them.
+fn binding_values(body: syn::Expr) -> Result<Vec<syn::LitStr>, AnalyzeError> {
+    match body {
+        // If the match arm expression just contains a literal, use that.
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(literal),
+            ..
+        }) => Ok(vec![literal]),
+
+        // If there's a tuple, treat each literal inside that tuple as a binding value.
+        syn::Expr::Tuple(tuple) => {
+            let mut values = Vec::new();
+            for elem in tuple.elems {
+                match elem {
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(literal),
+                        ..
+                    }) => values.push(literal),
+
+                    _ => return Err(AnalyzeError::ExpectedStringLiteral(elem.span())),
+                }
+            }
+            Ok(values)
+        }
+
+        body => Err(AnalyzeError::ExpectedStringLiteral(body.span())),
+    }
+}
+
+/// There must always be a matching amount of binding values in each match arm.
+/// Error if there are more or fewer values than binding names.
+fn check_value_count(
+    names: &[syn::Ident],
+    names_span: proc_macro2::Span,
+    values: &[syn::LitStr],
+    values_span: proc_macro2::Span,
+) -> Result<(), AnalyzeError> {
+    if names.len() != values.len() {
+        return Err(AnalyzeError::BindingNameValueLengthMismatch {
+            names: names.len(),
+            names_span,
+            values: values.len(),
+            values_span,
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the databases named by the pattern of a dialect arm, or `None` for `_`.
+fn dialect_databases(pattern: &syn::Pat) -> Result<Option<Vec<DatabaseType>>, AnalyzeError> {
+    let cases: Vec<_> = match pattern {
+        syn::Pat::Wild(_) => return Ok(None),
+        syn::Pat::Or(or) => or.cases.iter().collect(),
+        pattern => vec![pattern],
+    };
+
+    cases
+        .into_iter()
+        .map(|case| match case {
+            syn::Pat::Ident(syn::PatIdent {
+                ident,
+                by_ref: None,
+                mutability: None,
+                subpat: None,
+                ..
+            }) => match ident.to_string().as_str() {
+                "postgres" => Ok(DatabaseType::PostgreSql),
+                "mysql" => Ok(DatabaseType::MySql),
+                "sqlite" => Ok(DatabaseType::Sqlite),
+                _ => Err(AnalyzeError::InvalidDialect(ident.span())),
+            },
+            case => Err(AnalyzeError::InvalidDialect(case.span())),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Returns the label of a match arm, as given by its `#[label = "..."]` attribute or derived from
+/// its pattern.
+fn arm_label(arm: &syn::Arm) -> Result<String, AnalyzeError> {
+    for attr in &arm.attrs {
+        if !attr.path().is_ident("label") {
+            continue;
+        }
+
+        return match &attr.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                value:
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(label),
+                        ..
+                    }),
+                ..
+            }) => Ok(label.value()),
+            meta => Err(AnalyzeError::InvalidArmLabel(meta.span())),
+        };
+    }
+
+    Ok(arm
+        .pat
+        .to_token_stream()
+        .to_string()
+        .split_whitespace()
+        .collect())
+}
+
+pub(crate) mod compile_time_bindings {
     use std::collections::{HashMap, HashSet};
 
-    use super::{AnalyzeError, CompileTimeBinding};
+    use super::AnalyzeError;
 
-    pub(super) fn validate_compile_time_bindings(
-        compile_time_bindings: &[CompileTimeBinding],
-    ) -> Result<(), AnalyzeError> {
+    /// The names of the compile-time bindings referenced by the values of every binding, keyed by
+    /// the binding's name and along with the identifier it was declared with.
+    pub(crate) type ReferenceGraph<'a> = HashMap<String, (&'a syn::Ident, HashSet<String>)>;
+
+    /// Builds the reference graph from the given binding values of all arms.
+    pub(crate) fn reference_graph<'a>(
+        values: impl Iterator<Item = &'a (syn::Ident, syn::LitStr)>,
+    ) -> ReferenceGraph<'a> {
         let mut bindings = HashMap::new();
 
-        for (_, binding_values) in compile_time_bindings
-            .iter()
-            .flat_map(|bindings| &bindings.arms)
-        {
-            for (binding, value) in binding_values {
-                let name = binding.to_string();
+        for (binding, value) in values {
+            let name = binding.to_string();
 
-                let (_, references) = bindings
-                    .entry(name)
-                    .or_insert_with(|| (binding, HashSet::new()));
-                fill_references(references, &value.value());
-            }
+            let (_, references) = bindings
+                .entry(name)
+                .or_insert_with(|| (binding, HashSet::new()));
+            fill_references(references, &value.value());
         }
 
+        bindings
+    }
+
+    /// Validates the references between the given binding values of all arms.
+    pub(super) fn validate_compile_time_bindings<'a>(
+        values: impl Iterator<Item = &'a (syn::Ident, syn::LitStr)>,
+    ) -> Result<(), AnalyzeError> {
+        let bindings = reference_graph(values);
+
         for (name, (ident, _)) in &bindings {
             validate_references(&bindings, ident, &[], name)?;
         }
@@ -174,7 +441,8 @@ mod compile_time_bindings {
         Ok(())
     }
 
-    fn fill_references(references: &mut HashSet<String>, mut fragment: &str) {
+    /// Adds the names of all compile-time bindings referenced by `fragment` to `references`.
+    pub(crate) fn fill_references(references: &mut HashSet<String>, mut fragment: &str) {
         while let Some(start_idx) = fragment.find("{#") {
             fragment = &fragment[start_idx + 2..];
             if let Some(end_idx) = fragment.find("}") {
@@ -187,7 +455,7 @@ mod compile_time_bindings {
     }
 
     fn validate_references(
-        bindings: &HashMap<String, (&syn::Ident, HashSet<String>)>,
+        bindings: &ReferenceGraph,
         root_ident: &syn::Ident,
         path: &[&str],
         name: &str,
@@ -217,8 +485,6 @@ mod compile_time_bindings {
 
 #[cfg(test)]
 mod tests {
-    use quote::ToTokens;
-
     use super::*;
 
     #[test]
@@ -256,9 +522,10 @@ mod tests {
             assert_eq!(compile_time_binding.arms.len(), 1);
             {
                 let arm = &compile_time_binding.arms[0];
-                assert_eq!(arm.0.to_token_stream().to_string(), "bar");
+                assert_eq!(arm.pattern.to_token_stream().to_string(), "bar");
+                assert_eq!(arm.label, "bar");
                 assert_eq!(
-                    arm.1
+                    arm.values
                         .iter()
                         .map(|v| (
                             v.0.to_token_stream().to_string(),
@@ -284,7 +551,7 @@ mod tests {
                 compile_time_binding
                     .arms
                     .iter()
-                    .map(|v| v.0.to_token_stream().to_string())
+                    .map(|v| v.pattern.to_token_stream().to_string())
                     .collect::<Vec<_>>(),
                 &["d"],
             );
@@ -292,9 +559,10 @@ mod tests {
             assert_eq!(compile_time_binding.arms.len(), 1);
             {
                 let arm = &compile_time_binding.arms[0];
-                assert_eq!(arm.0.to_token_stream().to_string(), "d");
+                assert_eq!(arm.pattern.to_token_stream().to_string(), "d");
+                assert_eq!(arm.label, "d");
                 assert_eq!(
-                    arm.1
+                    arm.values
                         .iter()
                         .map(|v| (
                             v.0.to_token_stream().to_string(),
@@ -328,8 +596,8 @@ mod tests {
         let analyzed = analyze(parsed.clone()).unwrap_err();
 
         assert!(matches!(
-            analyzed,
-            AnalyzeError::DuplicatedCompileTimeBindingsFound { .. }
+            analyzed.as_slice(),
+            [AnalyzeError::DuplicatedCompileTimeBindingsFound { .. }]
         ));
     }
 
@@ -351,8 +619,140 @@ mod tests {
         let analyzed = analyze(parsed.clone()).unwrap_err();
 
         assert!(matches!(
-            analyzed,
-            AnalyzeError::CompileTimeBindingCycleDetected { .. }
+            analyzed.as_slice(),
+            [AnalyzeError::CompileTimeBindingCycleDetected { .. }]
+        ));
+    }
+
+    #[test]
+    fn arm_labels() {
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(
+            r##"
+                SomeType,
+                r#"{#a}"#,
+                #a = match foo {
+                    #[label = "with_limit"]
+                    Some(_) => "1",
+                    None => "2",
+                },
+            "##,
+        )
+        .unwrap();
+        let analyzed = analyze(parsed.clone()).unwrap();
+
+        assert_eq!(
+            analyzed.compile_time_bindings[0]
+                .arms
+                .iter()
+                .map(|arm| arm.label.as_str())
+                .collect::<Vec<_>>(),
+            &["with_limit", "None"],
+        );
+    }
+
+    #[test]
+    fn invalid_arm_label() {
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(
+            r##"
+                SomeType,
+                r#"{#a}"#,
+                #a = match foo {
+                    #[label(with_limit)]
+                    Some(_) => "1",
+                    None => "2",
+                },
+            "##,
+        )
+        .unwrap();
+        let analyzed = analyze(parsed.clone()).unwrap_err();
+
+        assert!(matches!(
+            analyzed.as_slice(),
+            [AnalyzeError::InvalidArmLabel(_)]
         ));
     }
+
+    #[test]
+    fn unknown_omitted_binding() {
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(
+            r##"
+                SomeType,
+                r#"{#order} {#page}"#,
+                #order = match foo {
+                    _ => "1",
+                },
+                #page = match bar {
+                    _ => "2",
+                },
+                count(omit = [#ordr, #limit]),
+            "##,
+        )
+        .unwrap();
+        let analyzed = analyze(parsed.clone()).unwrap_err();
+
+        assert!(matches!(
+            analyzed.as_slice(),
+            [
+                AnalyzeError::UnknownOmittedBinding { name: first, suggestion: Some(suggestion) },
+                AnalyzeError::UnknownOmittedBinding { name: second, suggestion: None },
+            ] if first == "ordr" && suggestion == "order" && second == "limit"
+        ));
+    }
+
+    #[test]
+    fn invalid_dialect() {
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "some SQL query",
+                #now = dialect {
+                    postgres => "NOW()",
+                    oracle => "SYSDATE",
+                },
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            analyze(parsed).unwrap_err().as_slice(),
+            [AnalyzeError::InvalidDialect(_)]
+        ));
+    }
+
+    #[test]
+    fn reports_all_errors() {
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(
+            r##"
+                SomeType,
+                r#"{#a} {#b} {#now}"#,
+                #a = match foo {
+                    #[label(with_limit)]
+                    Some(_) => "1",
+                    None => 2,
+                },
+                #(a, b) = match bar {
+                    _ => ("1", "2", "3"),
+                },
+                #now = dialect {
+                    oracle => "SYSDATE",
+                },
+            "##,
+        )
+        .unwrap();
+        let analyzed = analyze(parsed.clone()).unwrap_err();
+
+        assert!(
+            matches!(
+                analyzed.as_slice(),
+                [
+                    AnalyzeError::InvalidArmLabel(_),
+                    AnalyzeError::ExpectedStringLiteral(_),
+                    AnalyzeError::DuplicatedCompileTimeBindingsFound { .. },
+                    AnalyzeError::BindingNameValueLengthMismatch { .. },
+                    AnalyzeError::InvalidDialect(_),
+                ]
+            ),
+            "unexpected errors: {analyzed:?}"
+        );
+    }
 }
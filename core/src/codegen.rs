@@ -1,27 +1,39 @@
-use quote::{format_ident, quote};
+use std::collections::HashMap;
 
-use crate::expand::ExpandedConditionalQueryAs;
+use quote::{format_ident, quote, ToTokens};
+
+use crate::{
+    expand::{ExpandedConditionalQueryAs, RunTimeArgument},
+    lint::Lint,
+    DatabaseType,
+};
 
 /// This is the final step of the macro generation pipeline.
 /// The match arms and the respective query fragments are now used to generate a giant match
 /// statement, which covers all variants of the bindings' match statements' cartesian products.
+///
+/// If `capture_arguments` is set, every arm additionally passes `Debug` renderings of its
+/// arguments to the generated type.
+///
+/// Run-time bindings listed in `argument_expressions` are passed as the given expression instead
+/// of as the variable of the same name.
 pub(crate) fn codegen(
     expanded: ExpandedConditionalQueryAs,
     checked: bool,
+    capture_arguments: bool,
+    argument_expressions: &HashMap<String, syn::Expr>,
 ) -> proc_macro2::TokenStream {
     let mut match_arms = Vec::new();
+    let mut variant_sqls = Vec::new();
+    let mut variant_ids = Vec::new();
+    let mut variant_labels = Vec::new();
+    let mut variant_arguments = Vec::new();
     for (idx, arm) in expanded.match_arms.iter().enumerate() {
         let patterns = &arm.patterns;
-        let variant = format_ident!("Variant{}", idx);
+        let variant_index = proc_macro2::Literal::usize_unsuffixed(idx);
         let output_type = &expanded.output_type;
         let query_fragments = &arm.query_fragments;
-        let run_time_bindings =
-            arm.run_time_bindings
-                .iter()
-                .map(|(name, type_override)| match type_override {
-                    Some(ty) => quote!(#name as #ty),
-                    None => quote!(#name),
-                });
+        let run_time_bindings = query_arguments(&arm.run_time_bindings, argument_expressions);
 
         let query = if checked {
             format_ident!("query_as")
@@ -29,25 +41,93 @@ pub(crate) fn codegen(
             format_ident!("query_as_unchecked")
         };
 
-        match_arms.push(quote! {
-            (#(#patterns,)*) => {
-                ConditionalMap::#variant(
-                    ::sqlx::#query!(
-                        #output_type,
-                        #(#query_fragments)+*,
+        let mut variant = wrap_in_branches(
+            quote! {
+                ::sqlx::#query!(
+                    #output_type,
+                    #(#query_fragments)+*,
+                    #(#run_time_bindings),*
+                )
+            },
+            idx,
+            expanded.match_arms.len(),
+        );
+
+        if let Some(companions) = &arm.companions {
+            let query = if checked {
+                format_ident!("query")
+            } else {
+                format_ident!("query_unchecked")
+            };
+            let run_time_bindings: Vec<_> =
+                query_arguments(&companions.run_time_bindings, argument_expressions).collect();
+            let count_fragments = &companions.count_fragments;
+            let exists_fragments = &companions.exists_fragments;
+
+            let count = wrap_in_branches(
+                quote! {
+                    || ::sqlx::#query!(
+                        #(#count_fragments)+*,
                         #(#run_time_bindings),*
                     )
+                    .map(|row| row.count)
+                },
+                idx,
+                expanded.match_arms.len(),
+            );
+            let exists = wrap_in_branches(
+                quote! {
+                    || ::sqlx::#query!(
+                        #(#exists_fragments)+*,
+                        #(#run_time_bindings),*
+                    )
+                    .map(|_| ())
+                },
+                idx,
+                expanded.match_arms.len(),
+            );
+            variant = quote! {
+                ::sqlx_conditional_queries::exports::Companions::new(#variant, #count, #exists)
+            };
+        }
+
+        let captured_arguments = capture_arguments
+            .then(|| captured_arguments(&arm.run_time_bindings, argument_expressions));
+
+        match_arms.push(quote! {
+            (#(#patterns,)*) => {
+                ::sqlx_conditional_queries::exports::ConditionalMap::<_, ConditionalMapVariants>::new(
+                    #variant,
+                    #variant_index,
                 )
+                #captured_arguments
             },
         });
+
+        let sql: String = query_fragments
+            .iter()
+            .map(|fragment| fragment.value())
+            .collect();
+        variant_ids.push(&arm.id);
+        variant_labels.push(arm.label.clone());
+        variant_arguments.push(proc_macro2::Literal::usize_unsuffixed(
+            arm.run_time_bindings.len(),
+        ));
+        variant_sqls.push(syn::LitStr::new(&sql, proc_macro2::Span::call_site()));
     }
 
-    let conditional_map = build_conditional_map(expanded.match_arms.len());
     let match_expressions = expanded.match_expressions;
+    let variants = variants_impl(
+        &variant_sqls,
+        &variant_ids,
+        &variant_labels,
+        &variant_arguments,
+        &[],
+    );
 
     quote! {
         {
-            #conditional_map
+            #variants
 
             match (#(#match_expressions,)*) {
                 #(#match_arms)*
@@ -56,148 +136,349 @@ pub(crate) fn codegen(
     }
 }
 
-fn build_conditional_map(variant_count: usize) -> proc_macro2::TokenStream {
-    let function_params: Vec<_> = (0..variant_count)
-        .map(|index| format_ident!("F{}", index))
-        .collect();
-    let variants: Vec<_> = (0..variant_count)
-        .map(|index| format_ident!("Variant{}", index))
-        .collect();
+/// Generates the code for an invocation passing `database = any`, given its expansion for every
+/// database it supports.
+///
+/// Every arm evaluates to a closure which only builds the variant of the backend it's called
+/// with, so the arguments of the other backends' variants aren't bound.  The queries are built
+/// using `sqlx::query` and the output type's `FromRow` implementation, as SQLx can't check queries
+/// against `sqlx::Any`, which gives all variants the same type.  The closures of the arms still
+/// differ in their types, so they're wrapped in `Branch`es just like the variants in [`codegen`].
+///
+/// The variants of all databases are listed one database after the other, in the order of
+/// `expansions`.  The expansions only differ in their SQL and in the order of their arguments, so
+/// the patterns and match expressions are taken from the first one.
+pub(crate) fn codegen_any(
+    expansions: Vec<(DatabaseType, ExpandedConditionalQueryAs)>,
+    capture_arguments: bool,
+    argument_expressions: &HashMap<String, syn::Expr>,
+) -> proc_macro2::TokenStream {
+    let (_, first) = &expansions[0];
+    let output_type = &first.output_type;
+    let match_expressions = &first.match_expressions;
+    let arm_count = first.match_arms.len();
 
-    quote! {
-        enum ConditionalMap<'q, DB: ::sqlx::Database, A, #(#function_params),*> {
-            #(
-                #variants(
-                    ::sqlx::query::Map<'q, DB, #function_params, A>
-                ),
-            )*
+    let mut variant_sqls = Vec::new();
+    let mut variant_ids = Vec::new();
+    let mut variant_labels = Vec::new();
+    let mut variant_arguments = Vec::new();
+    let mut variant_databases = Vec::new();
+    for (database_type, expanded) in &expansions {
+        for arm in &expanded.match_arms {
+            let sql: String = arm
+                .query_fragments
+                .iter()
+                .map(|fragment| fragment.value())
+                .collect();
+            variant_sqls.push(syn::LitStr::new(&sql, proc_macro2::Span::call_site()));
+            variant_ids.push(&arm.id);
+            variant_labels.push(arm.label.clone());
+            variant_arguments.push(proc_macro2::Literal::usize_unsuffixed(
+                arm.run_time_bindings.len(),
+            ));
+            // The names of `sqlx::Database::NAME`, which `AnyConnection::backend_name` returns too.
+            variant_databases.push(match database_type {
+                DatabaseType::PostgreSql => "PostgreSQL",
+                DatabaseType::MySql => "MySQL",
+                DatabaseType::Sqlite => "SQLite",
+            });
         }
+    }
 
-        impl<'q, DB, A, O, #(#function_params),*> ConditionalMap<'q, DB, A, #(#function_params),*>
-        where
-            DB: ::sqlx::Database,
-            A: 'q + ::sqlx::IntoArguments<'q, DB> + ::std::marker::Send,
-            O: ::std::marker::Unpin + ::std::marker::Send,
-            #(
-                #function_params: ::std::ops::FnMut(DB::Row) -> ::sqlx::Result<O> + ::std::marker::Send,
-            )*
-        {
-            /// See [`sqlx::query::Map::fetch`]
-            pub fn fetch<'e, 'c: 'e, E>(
-                self,
-                executor: E,
-            ) -> ::sqlx_conditional_queries::exports::BoxStream<'e, ::sqlx::Result<O>>
-            where
-                'q: 'e,
-                E: 'e + ::sqlx::Executor<'c, Database = DB>,
-                DB: 'e,
-                O: 'e,
-                #(
-                    #function_params: 'e,
-                )*
-            {
-                match self {
-                    #(
-                        Self::#variants(map) => map.fetch(executor),
-                    )*
-                }
-            }
+    let mut match_arms = Vec::new();
+    for (arm_idx, arm) in first.match_arms.iter().enumerate() {
+        let patterns = &arm.patterns;
+        let backends = expansions
+            .iter()
+            .enumerate()
+            .map(|(database_idx, (database_type, expanded))| {
+                let arm = &expanded.match_arms[arm_idx];
+                let variant_index =
+                    proc_macro2::Literal::usize_unsuffixed(database_idx * arm_count + arm_idx);
+                let sql = &variant_sqls[database_idx * arm_count + arm_idx];
+                let backend = match database_type {
+                    DatabaseType::PostgreSql => format_ident!("PostgreSql"),
+                    DatabaseType::MySql => format_ident!("MySql"),
+                    DatabaseType::Sqlite => format_ident!("Sqlite"),
+                };
+                // Type overrides only matter to the checks of `query_as!`.
+                let arguments = any_arguments(&arm.run_time_bindings, argument_expressions);
 
-            /// See [`sqlx::query::Map::fetch_many`]
-            #[deprecated = "Only the SQLite driver supports multiple statements in one prepared statement and that behavior is deprecated. Use `sqlx::raw_sql()` instead. See https://github.com/launchbadge/sqlx/issues/3108 for discussion."]
-            pub fn fetch_many<'e, 'c: 'e, E>(
-                mut self,
-                executor: E,
-            ) -> ::sqlx_conditional_queries::exports::BoxStream<'e, ::sqlx::Result<::sqlx::Either<DB::QueryResult, O>>>
-            where
-                'q: 'e,
-                E: 'e + ::sqlx::Executor<'c, Database = DB>,
-                DB: 'e,
-                O: 'e,
-                #(
-                    #function_params: 'e,
-                )*
-            {
-                match self {
-                    #(
-                        Self::#variants(map) => {
-                            #[allow(deprecated)]
-                            map.fetch_many(executor)
-                        }
-                    )*
+                let mut variant = quote! {
+                    ::sqlx::query::<::sqlx::Any>(#sql)
+                        #(#arguments)*
+                        .try_map(
+                            (|row: ::sqlx::any::AnyRow| {
+                                <#output_type as ::sqlx::FromRow<'_, ::sqlx::any::AnyRow>>::from_row(&row)
+                            }) as fn(::sqlx::any::AnyRow) -> ::std::result::Result<#output_type, ::sqlx::Error>,
+                        )
+                };
+
+                if let Some(companions) = &arm.companions {
+                    let count_sql = concat_fragments(&companions.count_fragments);
+                    let exists_sql = concat_fragments(&companions.exists_fragments);
+                    let arguments =
+                        any_arguments(&companions.run_time_bindings, argument_expressions);
+                    let count = any_companion(
+                        &count_sql,
+                        &arguments,
+                        quote!(|row: ::sqlx::any::AnyRow| ::sqlx::Row::try_get::<i64, _>(&row, 0)),
+                        quote!(i64),
+                    );
+                    let exists = any_companion(
+                        &exists_sql,
+                        &arguments,
+                        quote!(|_: ::sqlx::any::AnyRow| ::std::result::Result::Ok(())),
+                        quote!(()),
+                    );
+                    variant = quote! {
+                        ::sqlx_conditional_queries::exports::Companions::new(#variant, #count, #exists)
+                    };
                 }
-            }
 
-            /// See [`sqlx::query::Map::fetch_all`]
-            pub async fn fetch_all<'e, 'c: 'e, E>(
-                self,
-                executor: E,
-            ) -> ::sqlx::Result<::std::vec::Vec<O>>
-            where
-                'q: 'e,
-                E: 'e + ::sqlx::Executor<'c, Database = DB>,
-                DB: 'e,
-                O: 'e,
-                #(
-                    #function_params: 'e,
-                )*
-            {
-                match self {
-                    #(
-                        Self::#variants(map) => map.fetch_all(executor).await,
-                    )*
+                let captured_arguments = capture_arguments
+                    .then(|| captured_arguments(&arm.run_time_bindings, argument_expressions));
+
+                quote! {
+                    ::sqlx_conditional_queries::exports::Backend::#backend => ::std::option::Option::Some(
+                        ::sqlx_conditional_queries::exports::ConditionalMap::<_, ConditionalMapVariants>::new(
+                            #variant,
+                            #variant_index,
+                        )
+                        #captured_arguments
+                    ),
                 }
-            }
+            });
 
-            /// See [`sqlx::query::Map::fetch_one`]
-            pub async fn fetch_one<'e, 'c: 'e, E>(
-                self,
-                executor: E,
-            ) -> ::sqlx::Result<O>
-            where
-                'q: 'e,
-                E: 'e + ::sqlx::Executor<'c, Database = DB>,
-                DB: 'e,
-                O: 'e,
-                #(
-                    #function_params: 'e,
-                )*
-            {
-                match self {
-                    #(
-                        Self::#variants(map) => map.fetch_one(executor).await,
-                    )*
+        let select = wrap_in_branches(
+            quote! {
+                |backend: ::sqlx_conditional_queries::exports::Backend| match backend {
+                    #(#backends)*
+                    #[allow(unreachable_patterns)]
+                    _ => ::std::option::Option::None,
                 }
+            },
+            arm_idx,
+            arm_count,
+        );
+        match_arms.push(quote! {
+            (#(#patterns,)*) => {
+                ::sqlx_conditional_queries::exports::AnyConditionalMap::new(#select)
+            },
+        });
+    }
+
+    let variants = variants_impl(
+        &variant_sqls,
+        &variant_ids,
+        &variant_labels,
+        &variant_arguments,
+        &variant_databases,
+    );
+
+    quote! {
+        {
+            #variants
+
+            match (#(#match_expressions,)*) {
+                #(#match_arms)*
             }
+        }
+    }
+}
 
-            /// See [`sqlx::query::Map::fetch_optional`]
-            pub async fn fetch_optional<'e, 'c: 'e, E>(
-                self,
-                executor: E,
-            ) -> ::sqlx::Result<::std::option::Option<O>>
-            where
-                'q: 'e,
-                E: 'e + ::sqlx::Executor<'c, Database = DB>,
-                DB: 'e,
-                O: 'e,
-                #(
-                    #function_params: 'e,
-                )*
+/// Returns the calls binding `run_time_bindings` to a query built for `sqlx::Any`.
+fn any_arguments(
+    run_time_bindings: &[RunTimeArgument],
+    argument_expressions: &HashMap<String, syn::Expr>,
+) -> Vec<proc_macro2::TokenStream> {
+    run_time_bindings
+        .iter()
+        .map(|argument| {
+            let argument = argument_expression(argument, argument_expressions);
+            quote!(.bind(&#argument))
+        })
+        .collect()
+}
+
+/// Returns the boxed closure building a companion query for `sqlx::Any`, which maps each row to
+/// an `output` using the closure `map_row`.
+///
+/// The closures of different variants differ in their types, which is why they're boxed.
+fn any_companion(
+    sql: &syn::LitStr,
+    arguments: &[proc_macro2::TokenStream],
+    map_row: proc_macro2::TokenStream,
+    output: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    quote! {
+        ::std::boxed::Box::new(|| {
+            ::sqlx::query::<::sqlx::Any>(#sql)
+                #(#arguments)*
+                .try_map(
+                    (#map_row) as fn(::sqlx::any::AnyRow) -> ::std::result::Result<#output, ::sqlx::Error>,
+                )
+        }) as ::std::boxed::Box<dyn ::std::ops::Fn() -> _ + ::std::marker::Send + '_>
+    }
+}
+
+/// Concatenates the fragments of a query into a single string literal.
+fn concat_fragments(fragments: &[syn::LitStr]) -> syn::LitStr {
+    let sql: String = fragments.iter().map(|fragment| fragment.value()).collect();
+    syn::LitStr::new(&sql, proc_macro2::Span::call_site())
+}
+/// Prepends a warning for every lint to the generated code.
+///
+/// Proc macros can't emit warnings on stable, so every warning is raised by referencing a
+/// deprecated constant whose deprecation note describes the lint, at the span of the binding.
+pub(crate) fn with_warnings(
+    lints: &[Lint],
+    output: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    if lints.is_empty() {
+        return output;
+    }
+
+    let warnings = lints.iter().map(|lint| {
+        let (Lint::UnusedBinding(name) | Lint::UnreachableBinding { name, .. }) = lint;
+        let note = match lint {
+            Lint::UnusedBinding(_) => format!(
+                "compile-time binding `#{name}` is never referenced, but still multiplies the number of variants"
+            ),
+            Lint::UnreachableBinding {
+                referenced_from, ..
+            } => format!(
+                "compile-time binding `#{name}` is only referenced from {}, which {} never used, but still multiplies the number of variants",
+                referenced_from.iter().map(|name| format!("`#{name}`")).collect::<Vec<_>>().join(", "),
+                if referenced_from.len() == 1 { "is" } else { "are" },
+            ),
+        };
+        let constant = format_ident!("unused_compile_time_binding", span = name.span());
+        quote! {
             {
-                match self {
-                    #(
-                        Self::#variants(map) => map.fetch_optional(executor).await,
-                    )*
-                }
+                #[deprecated(note = #note)]
+                #[allow(non_upper_case_globals)]
+                const unused_compile_time_binding: () = ();
+                let _ = #constant;
             }
         }
+    });
+
+    quote! {
+        {
+            #(#warnings)*
+            #output
+        }
+    }
+}
+
+/// Generates the `Variants` implementation describing all variants of an invocation, and
+/// registers its call site.
+///
+/// `databases` names the database of every variant of invocations passing `database = any`, and
+/// is empty for all others.
+fn variants_impl(
+    sqls: &[syn::LitStr],
+    ids: &[&String],
+    labels: &[String],
+    arguments: &[proc_macro2::Literal],
+    databases: &[&str],
+) -> proc_macro2::TokenStream {
+    let databases = (!databases.is_empty())
+        .then(|| quote!(const DATABASES: &'static [&'static str] = &[#(#databases),*];));
+    quote! {
+        struct ConditionalMapVariants;
+
+        impl ::sqlx_conditional_queries::exports::Variants for ConditionalMapVariants {
+            const VARIANTS: &'static [&'static str] = &[#(#sqls),*];
+            const IDS: &'static [&'static str] = &[#(#ids),*];
+            const LABELS: &'static [&'static str] = &[#(#labels),*];
+            const ARGUMENTS: &'static [usize] = &[#(#arguments),*];
+            #databases
+            const LOCATION: ::sqlx_conditional_queries::exports::SourceLocation =
+                ::sqlx_conditional_queries::exports::SourceLocation {
+                    file: ::std::file!(),
+                    line: ::std::line!(),
+                    column: ::std::column!(),
+                };
+        }
+
+        ::sqlx_conditional_queries::exports::register_call_site!(ConditionalMapVariants);
+    }
+}
+
+/// Returns the call passing the `Debug` renderings of `run_time_bindings` to the generated type.
+fn captured_arguments(
+    run_time_bindings: &[RunTimeArgument],
+    argument_expressions: &HashMap<String, syn::Expr>,
+) -> proc_macro2::TokenStream {
+    let renderings = run_time_bindings.iter().map(|argument| {
+        let name = argument_expression(argument, argument_expressions);
+        if argument.redact {
+            quote!(::std::string::String::from("<redacted>"))
+        } else {
+            quote!(::std::format!("{:?}", &#name))
+        }
+    });
+    quote!(.with_arguments(::std::vec![#(#renderings),*]))
+}
+
+/// Returns the arguments passed to `query_as!` for the given run-time bindings.
+fn query_arguments<'a>(
+    run_time_bindings: &'a [RunTimeArgument],
+    argument_expressions: &'a HashMap<String, syn::Expr>,
+) -> impl Iterator<Item = proc_macro2::TokenStream> + 'a {
+    run_time_bindings.iter().map(|argument| {
+        let name = argument_expression(argument, argument_expressions);
+        match &argument.type_override {
+            Some(ty) => quote!(#name as #ty),
+            None => quote!(#name),
+        }
+    })
+}
+
+/// Returns the expression passed for `argument`, which is usually just its name.
+fn argument_expression(
+    argument: &RunTimeArgument,
+    argument_expressions: &HashMap<String, syn::Expr>,
+) -> proc_macro2::TokenStream {
+    match argument_expressions.get(&argument.name.to_string()) {
+        Some(expression) => quote!((#expression)),
+        None => argument.name.to_token_stream(),
     }
 }
 
+/// Wraps the query of the variant at `index` in the `Branch`es leading to it.
+///
+/// The variants form the leaves of a balanced binary tree, which keeps both the nesting depth of
+/// the generated type and the number of matches needed to dispatch to a variant logarithmic in the
+/// number of variants.
+fn wrap_in_branches(
+    query: proc_macro2::TokenStream,
+    index: usize,
+    variant_count: usize,
+) -> proc_macro2::TokenStream {
+    let mut path = Vec::new();
+    let (mut start, mut end) = (0, variant_count);
+    while end - start > 1 {
+        let middle = start + (end - start).div_ceil(2);
+        if index < middle {
+            path.push(format_ident!("Left"));
+            end = middle;
+        } else {
+            path.push(format_ident!("Right"));
+            start = middle;
+        }
+    }
+
+    path.into_iter().rev().fold(
+        query,
+        |query, side| quote!(::sqlx_conditional_queries::exports::Branch::#side(#query)),
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::DatabaseType;
-
     use super::*;
 
     #[rstest::rstest]
@@ -225,8 +506,8 @@ mod tests {
         .unwrap();
         let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
         let lowered = crate::lower::lower(analyzed);
-        let expanded = crate::expand::expand(database_type, lowered).unwrap();
-        let _codegened = codegen(expanded, checked);
+        let expanded = crate::expand::expand(database_type, &database_type, lowered, None).unwrap();
+        let _codegened = codegen(expanded, checked, false, &HashMap::new());
     }
 
     #[rstest::rstest]
@@ -246,8 +527,8 @@ mod tests {
         .unwrap();
         let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
         let lowered = crate::lower::lower(analyzed);
-        let expanded = crate::expand::expand(database_type, lowered).unwrap();
-        let codegened = codegen(expanded, checked);
+        let expanded = crate::expand::expand(database_type, &database_type, lowered, None).unwrap();
+        let codegened = codegen(expanded, checked, false, &HashMap::new());
 
         let stringified = codegened.to_string();
         assert!(
@@ -255,4 +536,88 @@ mod tests {
             "binding type override was not correctly generated: {stringified}"
         );
     }
+
+    #[test]
+    fn variants_form_balanced_tree() {
+        let paths: Vec<_> = (0..5)
+            .map(|index| {
+                wrap_in_branches(quote!(query), index, 5)
+                    .to_string()
+                    .replace(":: sqlx_conditional_queries :: exports :: Branch :: ", "")
+            })
+            .collect();
+
+        assert_eq!(
+            paths,
+            [
+                "Left (Left (Left (query)))",
+                "Left (Left (Right (query)))",
+                "Left (Right (query))",
+                "Right (Left (query))",
+                "Right (Right (query))",
+            ]
+        );
+    }
+
+    #[test]
+    fn variant_sql() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "SELECT {#a} FROM table WHERE id = {id}",
+                #a = match c {
+                    d => "e",
+                    f => "g",
+                },
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let expanded = crate::expand::expand(
+            DatabaseType::PostgreSql,
+            &DatabaseType::PostgreSql,
+            lowered,
+            None,
+        )
+        .unwrap();
+        let codegened = codegen(expanded, true, false, &HashMap::new());
+
+        let stringified = codegened.to_string();
+        assert!(
+            stringified.contains(
+                r#"const VARIANTS : & 'static [& 'static str] = & ["SELECT e FROM table WHERE id = $1" , "SELECT g FROM table WHERE id = $1"] ;"#
+            ),
+            "variant SQL was not correctly generated: {stringified}"
+        );
+    }
+
+    #[test]
+    fn captured_arguments() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "SELECT * FROM users WHERE name = {name} AND password = {password:redact}",
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let expanded = crate::expand::expand(
+            DatabaseType::PostgreSql,
+            &DatabaseType::PostgreSql,
+            lowered,
+            None,
+        )
+        .unwrap();
+        let codegened = codegen(expanded, true, true, &HashMap::new());
+
+        let stringified = codegened.to_string();
+        assert!(
+            stringified.contains(
+                r#". with_arguments (:: std :: vec ! [:: std :: format ! ("{:?}" , & name) , :: std :: string :: String :: from ("<redacted>")])"#
+            ),
+            "captured arguments were not correctly generated: {stringified}"
+        );
+    }
 }
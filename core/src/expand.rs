@@ -1,15 +1,132 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::Range,
+};
 
-use crate::{lower::LoweredConditionalQueryAs, DatabaseType};
+use crate::{
+    lower::LoweredConditionalQueryAs, placeholder::PlaceholderStrategy, suggest, DatabaseType,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ExpandError {
-    #[error("missing compile-time binding: {0}")]
-    MissingCompileTimeBinding(String, proc_macro2::Span),
+    #[error("missing compile-time binding: {name}")]
+    MissingCompileTimeBinding {
+        name: String,
+        /// The most similarly named compile-time binding, if any.
+        suggestion: Option<String>,
+        location: QueryLocation,
+    },
     #[error("missing binding closing brace")]
-    MissingBindingClosingBrace(proc_macro2::Span),
+    MissingBindingClosingBrace(QueryLocation),
     #[error("failed to parse type override in binding reference: {0}")]
-    BindingReferenceTypeOverrideParseError(proc_macro2::LexError, proc_macro2::Span),
+    BindingReferenceTypeOverrideParseError(proc_macro2::LexError, QueryLocation),
+    #[error("invalid run-time binding name: `{0}`")]
+    InvalidRunTimeBindingName(String, QueryLocation),
+    #[error("conflicting type overrides for run-time binding `{name}`")]
+    ConflictingTypeOverrides {
+        name: String,
+        /// The type override of the first reference, if any.
+        first: Option<String>,
+        /// The conflicting type override, if any.
+        second: Option<String>,
+        location: QueryLocation,
+    },
+    #[error("missing type in SQL cast of run-time binding `{0}`")]
+    MissingSqlCastType(String, QueryLocation),
+    #[error("SQL comment format must not contain `*/`: {0}")]
+    InvalidSqlCommentFormat(String),
+    #[error("dialect binding `{names}` has no arm for {database_type:?}")]
+    MissingDialectArm {
+        names: String,
+        database_type: DatabaseType,
+        span: proc_macro2::Span,
+    },
+}
+
+/// The location of a binding reference within the query string, or within the string literal of a
+/// compile-time binding it was substituted from.
+#[derive(Clone, Debug)]
+pub struct QueryLocation {
+    /// The span of the reference itself if the compiler supports subspans of literals, and of the
+    /// whole string literal otherwise.
+    pub span: proc_macro2::Span,
+    /// The text of the reference, e.g. `{#name}`.
+    pub snippet: String,
+    /// The 1-based line of the reference within the string literal.
+    pub line: usize,
+    /// The 1-based column of the reference within its line, counted in characters.
+    pub column: usize,
+}
+
+impl fmt::Display for QueryLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` at line {}, column {}",
+            self.snippet, self.line, self.column
+        )
+    }
+}
+
+/// A sqlcommenter-style comment appended to the SQL of every variant, which allows attributing
+/// database load to a specific call site and variant.
+#[derive(Clone, Debug)]
+pub struct SqlComment {
+    /// The contents of the comment.
+    ///
+    /// `{variant}`, `{label}`, `{site}`, `{file}` and `{line}` are replaced by the variant's
+    /// identifier, its label, the call site as `file:line`, and its file and line respectively.
+    /// All values are URL-encoded, as required by sqlcommenter.
+    ///
+    /// The default format only includes the file, since the SQL of a variant is also the key of
+    /// SQLx's offline data, which would otherwise be invalidated by edits above the invocation.
+    pub format: String,
+    /// The file containing the macro invocation.
+    pub file: String,
+    /// The line of the macro invocation.
+    pub line: usize,
+}
+
+impl SqlComment {
+    pub const DEFAULT_FORMAT: &'static str = "cq_variant='{variant}', cq_site='{file}'";
+
+    /// Renders the comment for the variant with the given identifier and label.
+    fn render(&self, id: &str, label: &str) -> String {
+        let site = format!("{}:{}", self.file, self.line);
+        let comment = self
+            .format
+            .replace("{variant}", &url_encode(id))
+            .replace("{label}", &url_encode(label))
+            .replace("{site}", &url_encode(&site))
+            .replace("{file}", &url_encode(&self.file))
+            .replace("{line}", &self.line.to_string());
+        format!(" /*{comment}*/")
+    }
+}
+
+/// Percent-encodes everything but unreserved characters and the separators commonly found in
+/// labels and paths, which in particular prevents values from closing the comment or the quotes
+/// around them.
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'~'
+            | b','
+            | b'#'
+            | b':'
+            | b'/' => encoded.push(char::from(byte)),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
 }
 
 #[derive(Debug)]
@@ -22,96 +139,188 @@ pub(crate) struct ExpandedConditionalQueryAs {
 #[derive(Debug)]
 pub(crate) struct MatchArm {
     pub(crate) patterns: Vec<syn::Pat>,
+    pub(crate) id: String,
+    pub(crate) label: String,
     pub(crate) query_fragments: Vec<syn::LitStr>,
-    pub(crate) run_time_bindings: Vec<(syn::Ident, Option<proc_macro2::TokenStream>)>,
+    pub(crate) run_time_bindings: Vec<RunTimeArgument>,
+    /// The companion queries of this arm, if the `count` option was given.
+    pub(crate) companions: Option<Companions>,
+}
+
+/// The companion queries of a single arm, which wrap its SQL to count the rows it returns or to
+/// check whether it returns any.
+#[derive(Debug)]
+pub(crate) struct Companions {
+    pub(crate) count_fragments: Vec<syn::LitStr>,
+    pub(crate) exists_fragments: Vec<syn::LitStr>,
+    pub(crate) run_time_bindings: Vec<RunTimeArgument>,
+}
+
+impl Companions {
+    fn new(
+        database_type: DatabaseType,
+        fragments: Vec<syn::LitStr>,
+        run_time_bindings: Vec<RunTimeArgument>,
+        comment: Option<&str>,
+    ) -> Self {
+        // The `!` forces the columns to be non-null, which SQLx can't infer for aggregates.
+        let (count_column, exists_column) = match database_type {
+            DatabaseType::PostgreSql | DatabaseType::Sqlite => (r#""count!""#, r#""exists!""#),
+            DatabaseType::MySql => ("`count!`", "`exists!`"),
+        };
+        let comment = comment.unwrap_or_default();
+        let wrap = |prefix: String, suffix: String| {
+            let span = proc_macro2::Span::call_site();
+            let mut wrapped = vec![syn::LitStr::new(&prefix, span)];
+            wrapped.extend(fragments.iter().cloned());
+            wrapped.push(syn::LitStr::new(&suffix, span));
+            wrapped
+        };
+
+        Self {
+            count_fragments: wrap(
+                format!("SELECT COUNT(*) AS {count_column} FROM ("),
+                format!(") AS conditional_query_count{comment}"),
+            ),
+            exists_fragments: wrap(
+                format!("SELECT 1 AS {exists_column} FROM ("),
+                format!(") AS conditional_query_exists LIMIT 1{comment}"),
+            ),
+            run_time_bindings,
+        }
+    }
+}
+
+/// A single argument passed to `query_as!`, in placeholder order.
+#[derive(Debug)]
+pub(crate) struct RunTimeArgument {
+    pub(crate) name: syn::Ident,
+    pub(crate) type_override: Option<proc_macro2::TokenStream>,
+    /// Whether the binding was marked using `{name:redact}`, which keeps its value out of
+    /// captured arguments.
+    pub(crate) redact: bool,
 }
 
 /// Corresponds to a single run-time binding name.
 #[derive(Debug)]
 struct RunTimeBinding {
+    /// The name of the binding, which is also the variable holding its value.
+    name: syn::Ident,
+
     /// List of all argument index positions at which this binding needs to be bound.
     ///
-    /// - For PostgreSQL only contains one element.
-    /// - For MySQL and SQLite it contains one index for each time the binding was referenced.
+    /// - If the placeholders reuse arguments, such as PostgreSQL's and SQLite's, it only contains
+    ///   one element.
+    /// - Otherwise, such as for MySQL, it contains one index for each time the binding was
+    ///   referenced.
     indices: Vec<usize>,
 
     /// Type-override fragment to pass on To SQLx
     type_override: Option<proc_macro2::TokenStream>,
+
+    /// Whether any reference to this binding was marked as redacted.
+    redact: bool,
 }
 
-#[derive(Debug)]
-struct RunTimeBindings {
-    database_type: DatabaseType,
+struct RunTimeBindings<'a> {
+    placeholders: &'a dyn PlaceholderStrategy,
     counter: usize,
-    bindings: HashMap<syn::LitStr, RunTimeBinding>,
+    bindings: HashMap<String, RunTimeBinding>,
 }
 
-impl RunTimeBindings {
-    fn new(database_type: DatabaseType) -> Self {
+impl<'a> RunTimeBindings<'a> {
+    fn new(placeholders: &'a dyn PlaceholderStrategy) -> Self {
         Self {
-            database_type,
+            placeholders,
             counter: 0,
             bindings: Default::default(),
         }
     }
 
-    /// Returns a database-appropriate run-time binding string for the given binding name.
+    /// Returns the placeholder string for the given binding name, as rendered by the
+    /// [`PlaceholderStrategy`].
     ///
-    /// Database type selection is done based on the features this crate was built with.
+    /// - If the placeholders reuse arguments, such as PostgreSQL's `$1` and SQLite's `?1`,
+    ///   multiple references to the same parameter only need to be bound once.
+    /// - Otherwise, such as for the `?` of MySQL, the arguments need to specified in order and be
+    ///   duplicated for as many times as they're used.
     ///
-    /// - PostgreSQL uses 1-indexed references such as `$1`, which means that multiple references
-    ///   to the same parameter only need to be bound once.
-    /// - MySQL and SQLite always use `?` which means that the arguments need to specified in
-    ///   order and be duplicated for as many times as they're used.
+    /// All references to a binding have to agree on its type override, since they're all bound
+    /// from the same variable.
     fn get_binding_string(
         &mut self,
-        binding_name: syn::LitStr,
+        binding_name: syn::Ident,
         type_override: Option<proc_macro2::TokenStream>,
-    ) -> syn::LitStr {
-        match self.database_type {
-            DatabaseType::PostgreSql => {
-                let span = binding_name.span();
-                let binding = self.bindings.entry(binding_name).or_insert_with(|| {
-                    self.counter += 1;
-                    RunTimeBinding {
-                        indices: vec![self.counter],
-                        type_override,
-                    }
+        redact: bool,
+        location: impl FnOnce() -> QueryLocation,
+    ) -> Result<syn::LitStr, ExpandError> {
+        let span = binding_name.span();
+        let key = binding_name.to_string();
+        if let Some(binding) = self.bindings.get(&key) {
+            let render = |type_override: &Option<proc_macro2::TokenStream>| {
+                type_override.as_ref().map(ToString::to_string)
+            };
+            let (first, second) = (render(&binding.type_override), render(&type_override));
+            if first != second {
+                return Err(ExpandError::ConflictingTypeOverrides {
+                    name: key,
+                    first,
+                    second,
+                    location: location(),
                 });
-                syn::LitStr::new(&format!("${}", binding.indices.first().unwrap()), span)
             }
-            DatabaseType::MySql | DatabaseType::Sqlite => {
-                let span = binding_name.span();
+        }
+
+        let binding = if self.placeholders.reuses_arguments() {
+            self.bindings.entry(key).or_insert_with(|| {
                 self.counter += 1;
+                RunTimeBinding {
+                    name: binding_name,
+                    indices: vec![self.counter],
+                    type_override,
+                    redact: false,
+                }
+            })
+        } else {
+            self.counter += 1;
 
-                // For MySQL and SQLite bindings we need to specify the same argument multiple
-                // times if it's reused and so generate a unique index every time.  This ensures
-                // that `get_run_time_bindings` will generate the arguments in the correct order.
-                self.bindings
-                    .entry(binding_name)
-                    .and_modify(|binding| binding.indices.push(self.counter))
-                    .or_insert_with(|| RunTimeBinding {
-                        indices: vec![self.counter],
-                        type_override,
-                    });
-                syn::LitStr::new("?", span)
-            }
-        }
+            // If arguments aren't reused we need to specify the same argument multiple times and
+            // so generate a unique index every time.  This ensures that `get_arguments` will
+            // generate the arguments in the correct order.
+            self.bindings
+                .entry(key)
+                .and_modify(|binding| binding.indices.push(self.counter))
+                .or_insert_with(|| RunTimeBinding {
+                    name: binding_name,
+                    indices: vec![self.counter],
+                    type_override,
+                    redact: false,
+                })
+        };
+        binding.redact |= redact;
+        let index = *binding.indices.last().unwrap();
+        Ok(syn::LitStr::new(
+            &self.placeholders.placeholder(index),
+            span,
+        ))
     }
 
     /// Returns the `query_as!` arguments for all referenced run-time bindings.
-    fn get_arguments(self) -> Vec<(syn::Ident, Option<proc_macro2::TokenStream>)> {
+    fn get_arguments(self) -> Vec<RunTimeArgument> {
         let mut run_time_bindings: Vec<_> = self
             .bindings
-            .into_iter()
-            .flat_map(|(name, binding)| {
+            .into_values()
+            .flat_map(|binding| {
                 binding
                     .indices
                     .into_iter()
                     .map(|index| {
                         (
-                            syn::Ident::new(&name.value(), name.span()),
-                            binding.type_override.clone(),
+                            RunTimeArgument {
+                                name: binding.name.clone(),
+                                type_override: binding.type_override.clone(),
+                                redact: binding.redact,
+                            },
                             index,
                         )
                     })
@@ -119,11 +328,11 @@ impl RunTimeBindings {
             })
             .collect();
 
-        run_time_bindings.sort_by_key(|(_, _, index)| *index);
+        run_time_bindings.sort_by_key(|(_, index)| *index);
 
         run_time_bindings
             .into_iter()
-            .map(|(ident, type_override, _)| (ident, type_override))
+            .map(|(argument, _)| argument)
             .collect()
     }
 }
@@ -132,34 +341,134 @@ impl RunTimeBindings {
 /// each arm of the previously generated cartesian product of all bindings' match arms.
 ///
 /// The `{#binding_name}` placeholder are then replaced with the string literals from match clauses
-/// and  all `{scope_variable} placeholder are replaced with the positional variables of the respective
-/// database engine whose feature is enabled. For more info take a look at [RunTimeBindings].
+/// and  all `{scope_variable} placeholder are replaced with the positional variables rendered by
+/// `placeholders`. For more info take a look at [RunTimeBindings].
+///
+/// If `sql_comment` is given, it's appended to the SQL of every arm.
+///
+/// Errors don't stop the expansion, so that all of them can be reported at once.  Since every arm
+/// expands the same query string, errors occurring in several arms are only reported once.
 pub(crate) fn expand(
     database_type: DatabaseType,
+    placeholders: &dyn PlaceholderStrategy,
     lowered: LoweredConditionalQueryAs,
-) -> Result<ExpandedConditionalQueryAs, ExpandError> {
+    sql_comment: Option<&SqlComment>,
+) -> Result<ExpandedConditionalQueryAs, Vec<ExpandError>> {
+    let mut errors = Vec::new();
+
+    if let Some(sql_comment) = sql_comment {
+        if sql_comment.format.contains("*/") {
+            errors.push(ExpandError::InvalidSqlCommentFormat(
+                sql_comment.format.clone(),
+            ));
+        }
+    }
+
+    let mut dialect_values = HashMap::new();
+    for dialect_binding in &lowered.dialect_bindings {
+        let Some(values) = dialect_binding.resolve(database_type) else {
+            errors.push(ExpandError::MissingDialectArm {
+                names: dialect_binding.names.clone(),
+                database_type,
+                span: dialect_binding.span,
+            });
+            // Expand the names to nothing, so that their references aren't reported as missing
+            // as well.
+            dialect_values.extend(
+                dialect_binding
+                    .arms
+                    .iter()
+                    .flat_map(|arm| &arm.values)
+                    .map(|(name, _)| {
+                        (name.to_string(), syn::LitStr::new("", dialect_binding.span))
+                    }),
+            );
+            continue;
+        };
+        dialect_values.extend(
+            values
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone())),
+        );
+    }
+
     let mut match_arms = Vec::new();
 
-    for arm in lowered.match_arms {
-        let mut fragments = vec![lowered.query_string.clone()];
-        while fragments
-            .iter()
-            .any(|fragment| fragment.value().contains("{#"))
-        {
-            fragments = expand_compile_time_bindings(fragments, &arm.compile_time_bindings)?;
+    for mut arm in lowered.match_arms {
+        arm.compile_time_bindings.extend(dialect_values.clone());
+        let (mut expanded, run_time_bindings) = match expand_query(
+            placeholders,
+            &lowered.query_string,
+            &arm.compile_time_bindings,
+        ) {
+            Ok(expanded) => expanded,
+            Err(arm_errors) => {
+                errors.extend(arm_errors);
+                continue;
+            }
+        };
+
+        // The identifier is derived from the SQL without the comment, since the comment itself
+        // contains the identifier.
+        let sql: String = expanded.iter().map(|fragment| fragment.value()).collect();
+        let id = variant_id(&arm.label, &sql);
+        if let Some(sql_comment) = sql_comment {
+            expanded.push(syn::LitStr::new(
+                &sql_comment.render(&id, &arm.label),
+                proc_macro2::Span::call_site(),
+            ));
         }
 
-        // Substitute
-        let mut run_time_bindings = RunTimeBindings::new(database_type);
-        let expanded = expand_run_time_bindings(fragments, &mut run_time_bindings)?;
+        let companions = lowered
+            .count
+            .as_ref()
+            .map(|count| {
+                // Omitted bindings are replaced by empty strings, just like arms expanding to
+                // nothing.
+                let mut compile_time_bindings = arm.compile_time_bindings.clone();
+                for name in &count.omit {
+                    compile_time_bindings.insert(
+                        name.to_string(),
+                        syn::LitStr::new("", lowered.query_string.span()),
+                    );
+                }
+                let (fragments, run_time_bindings) =
+                    expand_query(placeholders, &lowered.query_string, &compile_time_bindings)?;
+                let comment = sql_comment.map(|sql_comment| sql_comment.render(&id, &arm.label));
+                Ok::<_, Vec<ExpandError>>(Companions::new(
+                    database_type,
+                    fragments,
+                    run_time_bindings,
+                    comment.as_deref(),
+                ))
+            })
+            .transpose();
+        let companions = match companions {
+            Ok(companions) => companions,
+            Err(companion_errors) => {
+                errors.extend(companion_errors);
+                continue;
+            }
+        };
 
         match_arms.push(MatchArm {
             patterns: arm.patterns,
+            id,
+            label: arm.label,
             query_fragments: expanded,
-            run_time_bindings: run_time_bindings.get_arguments(),
+            run_time_bindings,
+            companions,
         });
     }
 
+    if !errors.is_empty() {
+        // Spans are only comparable through their `Debug` output, which identifies their
+        // location in the source code.
+        let mut seen = HashSet::new();
+        errors.retain(|error| seen.insert(format!("{error:?}")));
+        return Err(errors);
+    }
+
     Ok(ExpandedConditionalQueryAs {
         output_type: lowered.output_type,
         match_expressions: lowered.match_expressions,
@@ -167,6 +476,172 @@ pub(crate) fn expand(
     })
 }
 
+/// Expands all compile-time and run-time bindings in `query_string`, returning the resulting query
+/// fragments along with the run-time arguments in placeholder order.
+fn expand_query(
+    placeholders: &dyn PlaceholderStrategy,
+    query_string: &syn::LitStr,
+    compile_time_bindings: &HashMap<String, syn::LitStr>,
+) -> Result<(Vec<syn::LitStr>, Vec<RunTimeArgument>), Vec<ExpandError>> {
+    let mut errors = Vec::new();
+
+    let mut fragments = vec![Fragment::new(query_string)];
+    while fragments
+        .iter()
+        .any(|fragment| fragment.text.contains("{#"))
+    {
+        fragments = expand_compile_time_bindings(fragments, compile_time_bindings, &mut errors);
+    }
+
+    // Substitute
+    let mut run_time_bindings = RunTimeBindings::new(placeholders);
+    let expanded = expand_run_time_bindings(fragments, &mut run_time_bindings, &mut errors);
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok((expanded, run_time_bindings.get_arguments()))
+}
+
+/// Returns the identifier of a variant, made up of its label and a hash of its SQL.
+///
+/// Unlike the position of a variant, this stays the same when arms are added to or removed from
+/// unrelated bindings.
+fn variant_id(label: &str, sql: &str) -> String {
+    // 32-bit FNV-1a, chosen since it's trivial and, unlike `DefaultHasher`, guaranteed to be
+    // stable across compiler versions.
+    let hash = sql.bytes().fold(0x811c9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+    });
+
+    if label.is_empty() {
+        format!("{hash:08x}")
+    } else {
+        format!("{label}#{hash:08x}")
+    }
+}
+
+/// A piece of the query string, which remembers the string literal it was taken from so that
+/// diagnostics can point at the binding references within it.
+#[derive(Clone)]
+struct Fragment {
+    /// The string literal this fragment was taken from, i.e. either the query string or the value
+    /// of a compile-time binding.
+    source: syn::LitStr,
+    /// The byte offset of this fragment within the value of `source`.
+    offset: usize,
+    text: String,
+}
+
+impl Fragment {
+    fn new(source: &syn::LitStr) -> Self {
+        Self {
+            source: source.clone(),
+            offset: 0,
+            text: source.value(),
+        }
+    }
+
+    /// Returns the given byte range of this fragment as a new fragment.
+    fn slice(&self, range: Range<usize>) -> Self {
+        Self {
+            source: self.source.clone(),
+            offset: self.offset + range.start,
+            text: self.text[range].to_string(),
+        }
+    }
+
+    fn span(&self) -> proc_macro2::Span {
+        self.source.span()
+    }
+
+    fn into_lit_str(self) -> syn::LitStr {
+        syn::LitStr::new(&self.text, self.source.span())
+    }
+
+    /// Returns the location of the given byte range of this fragment within its string literal.
+    ///
+    /// The span only covers the range itself if the compiler supports subspans of literals, which
+    /// is currently limited to nightly, and if the literal was actually written in the source code
+    /// rather than generated.
+    fn locate(&self, range: Range<usize>) -> QueryLocation {
+        let value = self.source.value();
+        let range = self.offset + range.start..self.offset + range.end;
+
+        let token = self.source.token();
+        let token_text = token.to_string();
+        let span = (self.span().source_text().as_ref() == Some(&token_text))
+            .then(|| {
+                let start = source_offset(&token_text, range.start)?;
+                let end = source_offset(&token_text, range.end)?;
+                token.subspan(start..end)
+            })
+            .flatten()
+            .unwrap_or_else(|| self.span());
+
+        let before = &value[..range.start];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        QueryLocation {
+            span,
+            snippet: value[range].to_string(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+/// Maps a byte offset within the value of a string literal to the corresponding byte offset within
+/// its source code `token`, accounting for the opening quote and for escape sequences.
+fn source_offset(token: &str, value_offset: usize) -> Option<usize> {
+    if let Some(raw) = token.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        return Some(1 + hashes + 1 + value_offset);
+    }
+
+    let mut value = 0;
+    let mut chars = token.char_indices().skip(1).peekable();
+    while let Some((index, c)) = chars.next() {
+        if value >= value_offset {
+            return (value == value_offset).then_some(index);
+        }
+        if c == '"' {
+            return None;
+        }
+        if c != '\\' {
+            value += c.len_utf8();
+            continue;
+        }
+
+        match chars.next()?.1 {
+            'x' => {
+                chars.nth(1)?;
+                value += 1;
+            }
+            'u' => {
+                let digits: String = chars
+                    .by_ref()
+                    .map(|(_, c)| c)
+                    .take_while(|c| *c != '}')
+                    .filter(|c| c.is_ascii_hexdigit())
+                    .collect();
+                value += char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?.len_utf8();
+            }
+            // A line continuation skips the line break along with any leading whitespace of the
+            // next line.
+            '\n' | '\r' => {
+                while chars
+                    .next_if(|(_, c)| matches!(c, ' ' | '\t' | '\n' | '\r'))
+                    .is_some()
+                {}
+            }
+            _ => value += 1,
+        }
+    }
+
+    None
+}
+
 /// This function takes the list of query fragments and substitutes all `{#binding_name}`
 /// occurrences with their literal strings from the respective match statements.
 ///
@@ -174,124 +649,214 @@ pub(crate) fn expand(
 /// this function is called from a while loop.
 /// Since this function might get called multiple times, some fragments might already be expanded
 /// at this point, despite the variable name.
+///
+/// Erroneous references are reported in `errors` and left out of the returned fragments.
 fn expand_compile_time_bindings(
-    unexpanded_fragments: Vec<syn::LitStr>,
+    unexpanded_fragments: Vec<Fragment>,
     compile_time_bindings: &HashMap<String, syn::LitStr>,
-) -> Result<Vec<syn::LitStr>, ExpandError> {
+    errors: &mut Vec<ExpandError>,
+) -> Vec<Fragment> {
     let mut expanded_fragments = Vec::new();
 
     for fragment in unexpanded_fragments {
-        let fragment_string = fragment.value();
-        let mut fragment_str = fragment_string.as_str();
+        let mut position = 0;
 
-        while let Some(start_of_binding) = fragment_str.find('{') {
+        while let Some(start_of_binding) = fragment.text[position..].find('{') {
             // We've hit either a compile-time or a run-time binding, so first we push any prefix
             // before the binding.
-            if !fragment_str[..start_of_binding].is_empty() {
-                expanded_fragments.push(syn::LitStr::new(
-                    &fragment_str[..start_of_binding],
-                    fragment.span(),
-                ));
-                fragment_str = &fragment_str[start_of_binding..];
+            let start_of_binding = position + start_of_binding;
+            if start_of_binding > position {
+                expanded_fragments.push(fragment.slice(position..start_of_binding));
             }
 
             // Then we find the matching closing brace.
-            let end_of_binding = if let Some(end_of_binding) = fragment_str.find('}') {
-                end_of_binding
-            } else {
-                return Err(ExpandError::MissingBindingClosingBrace(fragment.span()));
-            };
+            let end_of_binding =
+                if let Some(end_of_binding) = fragment.text[start_of_binding..].find('}') {
+                    start_of_binding + end_of_binding
+                } else {
+                    errors.push(ExpandError::MissingBindingClosingBrace(
+                        fragment.locate(start_of_binding..fragment.text.len()),
+                    ));
+                    position = fragment.text.len();
+                    break;
+                };
 
-            if fragment_str.chars().nth(1) == Some('#') {
+            if fragment.text[start_of_binding + 1..].starts_with('#') {
                 // If the binding is a compile-time binding, expand it.
-                let binding_name = &fragment_str[2..end_of_binding];
+                let binding_name = &fragment.text[start_of_binding + 2..end_of_binding];
                 if let Some(binding) = compile_time_bindings.get(binding_name) {
-                    expanded_fragments.push(binding.clone());
+                    expanded_fragments.push(Fragment::new(binding));
                 } else {
-                    return Err(ExpandError::MissingCompileTimeBinding(
-                        binding_name.to_string(),
-                        fragment.span(),
-                    ));
+                    let suggestion = suggest::closest_match(
+                        binding_name,
+                        compile_time_bindings.keys().map(String::as_str),
+                    );
+                    errors.push(ExpandError::MissingCompileTimeBinding {
+                        name: binding_name.to_string(),
+                        suggestion: suggestion.map(ToString::to_string),
+                        location: fragment.locate(start_of_binding..end_of_binding + 1),
+                    });
                 }
             } else {
                 // Otherwise push it as-is for the next pass.
-                expanded_fragments.push(syn::LitStr::new(
-                    &fragment_str[..end_of_binding + 1],
-                    fragment.span(),
-                ));
+                expanded_fragments.push(fragment.slice(start_of_binding..end_of_binding + 1));
             }
 
-            fragment_str = &fragment_str[end_of_binding + 1..];
+            position = end_of_binding + 1;
         }
 
         // Push trailing query fragment.
-        if !fragment_str.is_empty() {
-            expanded_fragments.push(syn::LitStr::new(fragment_str, fragment.span()));
+        if position < fragment.text.len() {
+            expanded_fragments.push(fragment.slice(position..fragment.text.len()));
         }
     }
 
-    Ok(expanded_fragments)
+    expanded_fragments
+}
+
+/// Splits a run-time binding reference into its name, its SQL cast, and the part following them,
+/// which holds the type override and redaction marker.
+///
+/// A double colon directly following the name starts an SQL cast, as in `{ids::int8[]}`, which
+/// extends to the next colon, so `{ids::int8[]:Vec<i64>}` additionally overrides the type passed
+/// to SQLx.  A single colon directly starts the part following the name, as in `{ids:Vec<i64>}`.
+fn split_binding_reference(reference: &str) -> (&str, Option<&str>, Option<&str>) {
+    let Some(offset) = reference.find(':') else {
+        return (reference.trim(), None, None);
+    };
+    let (name, rest) = reference.split_at(offset);
+    match rest.strip_prefix("::") {
+        Some(cast) => match cast.find(':') {
+            Some(end) => (
+                name.trim(),
+                Some(cast[..end].trim()),
+                Some(&cast[end + 1..]),
+            ),
+            None => (name.trim(), Some(cast.trim()), None),
+        },
+        None => (name.trim(), None, Some(&rest[1..])),
+    }
+}
+
+/// Splits the `redact` marker off the part of a run-time binding reference following its name,
+/// returning the remaining type override, if any.
+///
+/// Both `{name:redact}` and `{name:redact:Type}` are accepted, while a path such as
+/// `{name:redact::Type}` is left alone.
+fn strip_redaction_marker(suffix: &str) -> (Option<&str>, bool) {
+    let trimmed = suffix.trim_start();
+    match trimmed.strip_prefix("redact") {
+        Some(rest) if rest.trim().is_empty() => (None, true),
+        Some(rest) if rest.starts_with(':') && !rest.starts_with("::") => (Some(&rest[1..]), true),
+        _ => (Some(suffix), false),
+    }
 }
 
 /// Take all fragments and substitute any `{name}` occurrences with the respective database
-/// binding. Since the parameter syntax is different for various databases, [RunTimeBinding] is
-/// used in combination with feature flags to abstract this variance away.
+/// binding. Since the parameter syntax is different for various databases, [RunTimeBindings] is
+/// used in combination with a [PlaceholderStrategy] to abstract this variance away.
+///
+/// Erroneous references are reported in `errors` and left out of the returned query.
 fn expand_run_time_bindings(
-    unexpanded_fragments: Vec<syn::LitStr>,
+    unexpanded_fragments: Vec<Fragment>,
     run_time_bindings: &mut RunTimeBindings,
-) -> Result<Vec<syn::LitStr>, ExpandError> {
+    errors: &mut Vec<ExpandError>,
+) -> Vec<syn::LitStr> {
     let mut expanded_query = Vec::new();
 
     for fragment in unexpanded_fragments {
-        let fragment_string = fragment.value();
-        let mut fragment_str = fragment_string.as_str();
+        let mut position = 0;
 
-        while let Some(start_of_binding) = fragment_str.find('{') {
+        while let Some(start_of_binding) = fragment.text[position..].find('{') {
             // Otherwise we've hit a run-time binding, so first we push any prefix before the
             // binding.
-            expanded_query.push(syn::LitStr::new(
-                &fragment_str[..start_of_binding],
-                fragment.span(),
-            ));
+            let start_of_binding = position + start_of_binding;
+            expanded_query.push(fragment.slice(position..start_of_binding).into_lit_str());
 
             // Then we find the matching closing brace.
-            fragment_str = &fragment_str[start_of_binding + 1..];
-            let end_of_binding = if let Some(end_of_binding) = fragment_str.find('}') {
-                end_of_binding
-            } else {
-                return Err(ExpandError::MissingBindingClosingBrace(fragment.span()));
-            };
-
-            let binding_name = &fragment_str[..end_of_binding];
-            let (binding_name, type_override) = if let Some(offset) = binding_name.find(':') {
-                let (binding_name, type_override) = binding_name.split_at(offset);
-                let type_override = type_override[1..]
-                    .parse::<proc_macro2::TokenStream>()
-                    .map_err(|err| {
-                        ExpandError::BindingReferenceTypeOverrideParseError(err, fragment.span())
-                    })?;
-                (binding_name.trim(), Some(type_override))
-            } else {
-                (binding_name, None)
-            };
+            let end_of_binding =
+                if let Some(end_of_binding) = fragment.text[start_of_binding..].find('}') {
+                    start_of_binding + end_of_binding
+                } else {
+                    errors.push(ExpandError::MissingBindingClosingBrace(
+                        fragment.locate(start_of_binding..fragment.text.len()),
+                    ));
+                    position = fragment.text.len();
+                    break;
+                };
 
             // And finally we push a bound parameter argument
-            let binding = run_time_bindings.get_binding_string(
-                syn::LitStr::new(binding_name, fragment.span()),
-                type_override,
-            );
-            expanded_query.push(binding);
+            match expand_run_time_binding(
+                &fragment,
+                start_of_binding..end_of_binding + 1,
+                run_time_bindings,
+            ) {
+                Ok(binding) => expanded_query.push(binding),
+                Err(err) => errors.push(err),
+            }
 
-            fragment_str = &fragment_str[end_of_binding + 1..];
+            position = end_of_binding + 1;
         }
 
         // Push trailing query fragment.
-        if !fragment_str.is_empty() {
-            expanded_query.push(syn::LitStr::new(fragment_str, fragment.span()));
+        if position < fragment.text.len() {
+            expanded_query.push(fragment.slice(position..fragment.text.len()).into_lit_str());
         }
     }
 
-    Ok(expanded_query)
+    expanded_query
+}
+
+/// Returns the placeholder substituted for the run-time binding reference at `range` of
+/// `fragment`, including its braces.
+fn expand_run_time_binding(
+    fragment: &Fragment,
+    range: Range<usize>,
+    run_time_bindings: &mut RunTimeBindings,
+) -> Result<syn::LitStr, ExpandError> {
+    let location = || fragment.locate(range.clone());
+
+    let (binding_name, sql_cast, suffix) =
+        split_binding_reference(&fragment.text[range.start + 1..range.end - 1]);
+    let (type_override, redact) = match suffix {
+        Some(suffix) => strip_redaction_marker(suffix),
+        None => (None, false),
+    };
+    let type_override = type_override
+        .map(|type_override| {
+            type_override
+                .parse::<proc_macro2::TokenStream>()
+                .map_err(|err| ExpandError::BindingReferenceTypeOverrideParseError(err, location()))
+        })
+        .transpose()?;
+    if sql_cast.is_some_and(str::is_empty) {
+        return Err(ExpandError::MissingSqlCastType(
+            binding_name.to_string(),
+            location(),
+        ));
+    }
+
+    // Binding names become variable references in the generated code, so they have to be valid
+    // identifiers.
+    let binding_name = syn::parse_str::<syn::Ident>(binding_name)
+        .map(|mut ident| {
+            ident.set_span(fragment.span());
+            ident
+        })
+        .map_err(|_| {
+            ExpandError::InvalidRunTimeBindingName(binding_name.to_string(), location())
+        })?;
+    let binding =
+        run_time_bindings.get_binding_string(binding_name, type_override, redact, location)?;
+    Ok(match sql_cast {
+        Some(sql_cast) => syn::LitStr::new(
+            &run_time_bindings
+                .placeholders
+                .cast(&binding.value(), sql_cast),
+            binding.span(),
+        ),
+        None => binding,
+    })
 }
 
 #[cfg(test)]
@@ -322,7 +887,7 @@ mod tests {
         .unwrap();
         let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
         let lowered = crate::lower::lower(analyzed);
-        let expanded = expand(database_type, lowered).unwrap();
+        let expanded = expand(database_type, &database_type, lowered, None).unwrap();
 
         assert_eq!(
             expanded.match_arms[0]
@@ -350,13 +915,13 @@ mod tests {
         let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
             r#"
                 SomeType,
-                "some {foo:ty} {bar} {foo} query",
+                "some {foo:ty} {bar} {foo:ty} query",
             "#,
         )
         .unwrap();
         let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
         let lowered = crate::lower::lower(analyzed);
-        let expanded = expand(database_type, lowered).unwrap();
+        let expanded = expand(database_type, &database_type, lowered, None).unwrap();
 
         // Check that run-time binding references are generated properly.
         assert_eq!(
@@ -375,7 +940,16 @@ mod tests {
                     "\"$1\"",
                     "\" query\""
                 ],
-                DatabaseType::MySql | DatabaseType::Sqlite => &[
+                DatabaseType::Sqlite => &[
+                    "\"some \"",
+                    "\"?1\"",
+                    "\" \"",
+                    "\"?2\"",
+                    "\" \"",
+                    "\"?1\"",
+                    "\" query\""
+                ],
+                DatabaseType::MySql => &[
                     "\"some \"",
                     "\"?\"",
                     "\" \"",
@@ -391,16 +965,21 @@ mod tests {
         let run_time_bindings: Vec<_> = expanded.match_arms[0]
             .run_time_bindings
             .iter()
-            .map(|(ident, ts)| (ident.to_string(), ts.as_ref().map(|ts| ts.to_string())))
+            .map(|argument| {
+                (
+                    argument.name.to_string(),
+                    argument.type_override.as_ref().map(|ts| ts.to_string()),
+                )
+            })
             .collect();
         assert_eq!(
             run_time_bindings,
             match database_type {
-                DatabaseType::PostgreSql => vec![
+                DatabaseType::PostgreSql | DatabaseType::Sqlite => vec![
                     ("foo".to_string(), Some("ty".to_string())),
                     ("bar".to_string(), None),
                 ],
-                DatabaseType::MySql | DatabaseType::Sqlite => vec![
+                DatabaseType::MySql => vec![
                     ("foo".to_string(), Some("ty".to_string())),
                     ("bar".to_string(), None),
                     ("foo".to_string(), Some("ty".to_string())),
@@ -408,4 +987,629 @@ mod tests {
             }
         );
     }
+
+    /// Numbered placeholders such as Oracle's `:1`, optionally binding reused bindings again.
+    struct Numbered {
+        reuses_arguments: bool,
+    }
+
+    impl PlaceholderStrategy for Numbered {
+        fn placeholder(&self, index: usize) -> String {
+            format!(":{index}")
+        }
+
+        fn reuses_arguments(&self) -> bool {
+            self.reuses_arguments
+        }
+    }
+
+    #[rstest::rstest]
+    #[case(true, "some :1 :2 :1 query", &["foo", "bar"])]
+    #[case(false, "some :1 :2 :3 query", &["foo", "bar", "foo"])]
+    fn expands_custom_placeholders(
+        #[case] reuses_arguments: bool,
+        #[case] expected_sql: &str,
+        #[case] expected_arguments: &[&str],
+    ) {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "some {foo} {bar} {foo} query",
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let placeholders = Numbered { reuses_arguments };
+        let expanded = expand(DatabaseType::PostgreSql, &placeholders, lowered, None).unwrap();
+
+        let sql: String = expanded.match_arms[0]
+            .query_fragments
+            .iter()
+            .map(|fragment| fragment.value())
+            .collect();
+        assert_eq!(sql, expected_sql);
+        let arguments: Vec<_> = expanded.match_arms[0]
+            .run_time_bindings
+            .iter()
+            .map(|argument| argument.name.to_string())
+            .collect();
+        assert_eq!(arguments, expected_arguments);
+    }
+
+    #[rstest::rstest]
+    #[case(DatabaseType::PostgreSql)]
+    #[case(DatabaseType::MySql)]
+    #[case(DatabaseType::Sqlite)]
+    fn expands_redaction_markers(#[case] database_type: DatabaseType) {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "some {name} {password:redact} {token:redact:ty} {other:redact::Ty} {password} query",
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let expanded = expand(database_type, &database_type, lowered, None).unwrap();
+
+        let run_time_bindings: Vec<_> = expanded.match_arms[0]
+            .run_time_bindings
+            .iter()
+            .map(|argument| {
+                (
+                    argument.name.to_string(),
+                    argument.type_override.as_ref().map(|ts| ts.to_string()),
+                    argument.redact,
+                )
+            })
+            .collect();
+        let mut expected = vec![
+            ("name".to_string(), None, false),
+            ("password".to_string(), None, true),
+            ("token".to_string(), Some("ty".to_string()), true),
+            ("other".to_string(), Some("redact :: Ty".to_string()), false),
+        ];
+        if let DatabaseType::MySql = database_type {
+            expected.push(("password".to_string(), None, true));
+        }
+        assert_eq!(run_time_bindings, expected);
+    }
+
+    #[rstest::rstest]
+    #[case(
+        DatabaseType::PostgreSql,
+        "WHERE id = ANY($1::int8[]) AND name = $2::text AND id > $3"
+    )]
+    #[case(
+        DatabaseType::MySql,
+        "WHERE id = ANY(CAST(? AS int8[])) AND name = CAST(? AS text) AND id > ?"
+    )]
+    #[case(
+        DatabaseType::Sqlite,
+        "WHERE id = ANY(CAST(?1 AS int8[])) AND name = CAST(?2 AS text) AND id > ?3"
+    )]
+    fn expands_sql_casts(#[case] database_type: DatabaseType, #[case] expected: &str) {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "WHERE id = ANY({ids::int8[]:Vec<i64>}) AND name = {name::text:redact} AND id > {min:i64}",
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let expanded = expand(database_type, &database_type, lowered, None).unwrap();
+
+        let sql: String = expanded.match_arms[0]
+            .query_fragments
+            .iter()
+            .map(|fragment| fragment.value())
+            .collect();
+        assert_eq!(sql, expected);
+
+        let run_time_bindings: Vec<_> = expanded.match_arms[0]
+            .run_time_bindings
+            .iter()
+            .map(|argument| {
+                (
+                    argument.name.to_string(),
+                    argument.type_override.as_ref().map(|ts| ts.to_string()),
+                    argument.redact,
+                )
+            })
+            .collect();
+        assert_eq!(
+            run_time_bindings,
+            [
+                ("ids".to_string(), Some("Vec < i64 >".to_string()), false),
+                ("name".to_string(), None, true),
+                ("min".to_string(), Some("i64".to_string()), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_empty_sql_cast() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "WHERE id = {id::}",
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+
+        assert!(matches!(
+            expand(DatabaseType::PostgreSql, &DatabaseType::PostgreSql, lowered, None).unwrap_err().as_slice(),
+            [ExpandError::MissingSqlCastType(binding, _)] if binding == "id"
+        ));
+    }
+
+    #[rstest::rstest]
+    #[case("{1}", "1")]
+    #[case("{foo bar}", "foo bar")]
+    #[case("{}", "")]
+    #[case("{fn:_}", "fn")]
+    fn rejects_invalid_run_time_binding_names(#[case] reference: &str, #[case] expected: &str) {
+        let query = format!("SELECT * FROM users WHERE id = {reference}");
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(&format!(
+            "SomeType, {:?}",
+            query
+        ))
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+
+        assert!(matches!(
+            expand(DatabaseType::PostgreSql, &DatabaseType::PostgreSql, lowered, None).unwrap_err().as_slice(),
+            [ExpandError::InvalidRunTimeBindingName(name, _)] if name == expected
+        ));
+    }
+
+    #[rstest::rstest]
+    #[case(DatabaseType::PostgreSql)]
+    #[case(DatabaseType::MySql)]
+    #[case(DatabaseType::Sqlite)]
+    fn rejects_conflicting_type_overrides(#[case] database_type: DatabaseType) {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "SELECT * FROM users WHERE id = {id:_} OR parent_id = {id::int8} OR {id} IS NULL",
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+
+        let error = expand(database_type, &database_type, lowered, None).unwrap_err();
+        assert!(
+            error.len() == 2
+                && error.iter().all(|error| matches!(
+                    error,
+                    ExpandError::ConflictingTypeOverrides { name, first, second, .. }
+                        if name == "id" && first.as_deref() == Some("_") && second.is_none()
+                )),
+            "unexpected error: {error:?}"
+        );
+    }
+
+    #[test]
+    fn locates_binding_references() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r##"
+                SomeType,
+                r#"
+                    SELECT *
+                    FROM users {#filter}
+                    ORDER BY {#order}
+                "#,
+                #filter = match filter {
+                    _ => "",
+                },
+            "##,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+
+        let errors = expand(
+            DatabaseType::PostgreSql,
+            &DatabaseType::PostgreSql,
+            lowered,
+            None,
+        )
+        .unwrap_err();
+        let [ExpandError::MissingCompileTimeBinding { name, location, .. }] = errors.as_slice()
+        else {
+            panic!("expected a missing compile-time binding");
+        };
+        assert_eq!(name, "order");
+        assert_eq!(location.snippet, "{#order}");
+        assert_eq!((location.line, location.column), (4, 30));
+        assert_eq!(location.to_string(), "`{#order}` at line 4, column 30");
+    }
+
+    #[rstest::rstest]
+    #[case::plain(r#""WHERE name = {na me}""#, 14, 15)]
+    #[case::escapes(r#""WHERE\t\"name\" = {na me}""#, 15, 19)]
+    #[case::hex_escapes(r#""WHERE \x41 = {na me}""#, 10, 14)]
+    #[case::unicode_escapes(r#""WHERE \u{e9} = {na me}""#, 11, 16)]
+    #[case::line_continuation("\"WHERE \\\n    name = {na me}\"", 13, 20)]
+    #[case::raw(r###"r##"WHERE "name" = {na me}"##"###, 15, 19)]
+    #[case::end(r#""WHERE\tid""#, 8, 10)]
+    fn maps_value_offsets_to_source_offsets(
+        #[case] token: &str,
+        #[case] value_offset: usize,
+        #[case] expected: usize,
+    ) {
+        assert_eq!(source_offset(token, value_offset), Some(expected));
+    }
+
+    #[test]
+    fn rejects_value_offsets_past_the_end() {
+        assert_eq!(source_offset(r#""WHERE""#, 6), None);
+    }
+
+    #[test]
+    fn locates_binding_references_in_compile_time_bindings() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r##"
+                SomeType,
+                "SELECT * FROM users {#filter}",
+                #filter = match filter {
+                    _ => "WHERE id = {id:Vec<(i64>}",
+                },
+            "##,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+
+        let errors = expand(
+            DatabaseType::PostgreSql,
+            &DatabaseType::PostgreSql,
+            lowered,
+            None,
+        )
+        .unwrap_err();
+        let [ExpandError::BindingReferenceTypeOverrideParseError(_, location)] = errors.as_slice()
+        else {
+            panic!("expected a type override parse error");
+        };
+        assert_eq!(location.snippet, "{id:Vec<(i64>}");
+        assert_eq!((location.line, location.column), (1, 12));
+    }
+
+    #[test]
+    fn locates_missing_closing_braces() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "SELECT * FROM users WHERE id = {id",
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+
+        let errors = expand(
+            DatabaseType::PostgreSql,
+            &DatabaseType::PostgreSql,
+            lowered,
+            None,
+        )
+        .unwrap_err();
+        let [ExpandError::MissingBindingClosingBrace(location)] = errors.as_slice() else {
+            panic!("expected a missing closing brace");
+        };
+        assert_eq!(location.snippet, "{id");
+        assert_eq!((location.line, location.column), (1, 32));
+    }
+
+    #[test]
+    fn reports_all_errors_once() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r##"
+                SomeType,
+                "SELECT * FROM users WHERE id = {1} {#fliter} ORDER BY {#order} {#limit",
+                #filter = match filter {
+                    true => "AND active",
+                    false => "",
+                },
+                #order = match order {
+                    true => "name",
+                    false => "id",
+                },
+            "##,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+
+        let errors = expand(
+            DatabaseType::PostgreSql,
+            &DatabaseType::PostgreSql,
+            lowered,
+            None,
+        )
+        .unwrap_err();
+        let mut messages: Vec<_> = errors.iter().map(ToString::to_string).collect();
+        messages.sort();
+        assert_eq!(
+            messages,
+            &[
+                "invalid run-time binding name: `1`",
+                "missing binding closing brace",
+                "missing compile-time binding: fliter",
+            ],
+        );
+        assert!(errors.iter().any(|error| matches!(
+            error,
+            ExpandError::MissingCompileTimeBinding { suggestion: Some(suggestion), .. }
+                if suggestion == "filter"
+        )));
+    }
+
+    #[test]
+    fn suggests_nothing_for_dissimilar_compile_time_bindings() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r##"
+                SomeType,
+                "SELECT * FROM users ORDER BY {#direction}",
+                #order = match order {
+                    _ => "id",
+                },
+            "##,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+
+        assert!(matches!(
+            expand(
+                DatabaseType::PostgreSql,
+                &DatabaseType::PostgreSql,
+                lowered,
+                None
+            )
+            .unwrap_err()
+            .as_slice(),
+            [ExpandError::MissingCompileTimeBinding {
+                suggestion: None,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn variant_ids() {
+        assert_eq!(variant_id("", "SELECT 1"), "bce9e8b2");
+        assert_eq!(
+            variant_id("with_limit,Asc", "SELECT 1"),
+            "with_limit,Asc#bce9e8b2"
+        );
+        assert_ne!(variant_id("", "SELECT 1"), variant_id("", "SELECT 2"));
+    }
+
+    #[test]
+    fn appends_sql_comment() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "SELECT {#a} FROM table",
+                #a = match c {
+                    #[label = "it's"]
+                    d => "e",
+                    f => "g",
+                },
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let sql_comment = SqlComment {
+            format: SqlComment::DEFAULT_FORMAT.to_string(),
+            file: "src/main.rs".to_string(),
+            line: 12,
+        };
+        let expanded = expand(
+            DatabaseType::PostgreSql,
+            &DatabaseType::PostgreSql,
+            lowered,
+            Some(&sql_comment),
+        )
+        .unwrap();
+
+        let sql: Vec<String> = expanded
+            .match_arms
+            .iter()
+            .map(|arm| arm.query_fragments.iter().map(|qs| qs.value()).collect())
+            .collect();
+        assert_eq!(
+            sql,
+            [
+                "SELECT e FROM table /*cq_variant='it%27s#7fac2166', cq_site='src/main.rs'*/",
+                "SELECT g FROM table /*cq_variant='f#bfef8e64', cq_site='src/main.rs'*/",
+            ]
+        );
+        assert_eq!(
+            expanded.match_arms[1].id,
+            variant_id("f", "SELECT g FROM table")
+        );
+
+        let sql_comment = SqlComment {
+            format: "cq_site='{site}', cq_line='{line}'".to_string(),
+            ..sql_comment
+        };
+        let analyzed = crate::analyze::analyze(parsed).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let expanded = expand(
+            DatabaseType::PostgreSql,
+            &DatabaseType::PostgreSql,
+            lowered,
+            Some(&sql_comment),
+        )
+        .unwrap();
+        let sql: String = expanded.match_arms[0]
+            .query_fragments
+            .iter()
+            .map(|qs| qs.value())
+            .collect();
+        assert_eq!(
+            sql,
+            "SELECT e FROM table /*cq_site='src/main.rs:12', cq_line='12'*/"
+        );
+    }
+
+    #[test]
+    fn rejects_sql_comment_closing_the_comment() {
+        let parsed =
+            syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(r#"SomeType, "SELECT 1""#)
+                .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let sql_comment = SqlComment {
+            format: "*/ DROP TABLE users; /*".to_string(),
+            file: "src/main.rs".to_string(),
+            line: 12,
+        };
+
+        assert!(matches!(
+            expand(
+                DatabaseType::PostgreSql,
+                &DatabaseType::PostgreSql,
+                lowered,
+                Some(&sql_comment)
+            )
+            .unwrap_err()
+            .as_slice(),
+            [ExpandError::InvalidSqlCommentFormat(_)]
+        ));
+    }
+
+    #[rstest::rstest]
+    #[case(
+        DatabaseType::PostgreSql,
+        "SELECT NOW() FROM users WHERE name ILIKE $1"
+    )]
+    #[case(DatabaseType::MySql, "SELECT NOW() FROM users WHERE name LIKE ?")]
+    #[case(
+        DatabaseType::Sqlite,
+        "SELECT CURRENT_TIMESTAMP FROM users WHERE name LIKE ?1"
+    )]
+    fn expands_dialect_bindings(#[case] database_type: DatabaseType, #[case] expected: &str) {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "SELECT {#now} FROM users WHERE name {#like} {name}",
+                #now = dialect {
+                    postgres | mysql => "NOW()",
+                    sqlite => "CURRENT_TIMESTAMP",
+                },
+                #like = dialect {
+                    postgres => "ILIKE",
+                    _ => "LIKE",
+                },
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let expanded = expand(database_type, &database_type, lowered, None).unwrap();
+
+        assert_eq!(expanded.match_arms.len(), 1);
+        let sql: String = expanded.match_arms[0]
+            .query_fragments
+            .iter()
+            .map(|qs| qs.value())
+            .collect();
+        assert_eq!(sql, expected);
+    }
+
+    #[test]
+    fn rejects_missing_dialect_arm() {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "SELECT * FROM users {#returning}",
+                #returning = dialect {
+                    postgres | sqlite => "RETURNING id",
+                },
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+
+        assert!(matches!(
+            expand(DatabaseType::MySql, &DatabaseType::MySql, lowered, None)
+                .unwrap_err()
+                .as_slice(),
+            [ExpandError::MissingDialectArm {
+                database_type: DatabaseType::MySql,
+                ..
+            }]
+        ));
+    }
+
+    #[rstest::rstest]
+    #[case(DatabaseType::PostgreSql)]
+    #[case(DatabaseType::MySql)]
+    #[case(DatabaseType::Sqlite)]
+    fn expands_companions(#[case] database_type: DatabaseType) {
+        let parsed = syn::parse_str::<crate::parse::ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "SELECT * FROM users WHERE name = {name} {#page}",
+                #page = match page {
+                    Some(_) => "LIMIT {limit} OFFSET {offset}",
+                    None => "",
+                },
+                count(omit = [#page]),
+            "#,
+        )
+        .unwrap();
+        let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
+        let lowered = crate::lower::lower(analyzed);
+        let expanded = expand(database_type, &database_type, lowered, None).unwrap();
+
+        let companions = expanded.match_arms[0].companions.as_ref().unwrap();
+        let count: String = companions
+            .count_fragments
+            .iter()
+            .map(|qs| qs.value())
+            .collect();
+        let exists: String = companions
+            .exists_fragments
+            .iter()
+            .map(|qs| qs.value())
+            .collect();
+        let placeholder = match database_type {
+            DatabaseType::PostgreSql => "$1",
+            DatabaseType::Sqlite => "?1",
+            DatabaseType::MySql => "?",
+        };
+        let (count_column, exists_column) = match database_type {
+            DatabaseType::PostgreSql | DatabaseType::Sqlite => (r#""count!""#, r#""exists!""#),
+            DatabaseType::MySql => ("`count!`", "`exists!`"),
+        };
+        assert_eq!(
+            count,
+            format!(
+                "SELECT COUNT(*) AS {count_column} FROM (SELECT * FROM users WHERE name = {placeholder} ) AS conditional_query_count"
+            )
+        );
+        assert_eq!(
+            exists,
+            format!(
+                "SELECT 1 AS {exists_column} FROM (SELECT * FROM users WHERE name = {placeholder} ) AS conditional_query_exists LIMIT 1"
+            )
+        );
+
+        // The pagination's run-time bindings are only referenced by the main query.
+        assert_eq!(expanded.match_arms[0].run_time_bindings.len(), 3);
+        assert_eq!(companions.run_time_bindings.len(), 1);
+    }
 }
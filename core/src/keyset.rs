@@ -0,0 +1,402 @@
+//! Keyset pagination, which is desugared into an ordinary compile-time binding.
+//!
+//! ```text
+//! #(after, page) = keyset {
+//!     keys: [created_at, id],
+//!     direction: direction,
+//!     cursor: after,
+//!     limit: page_size,
+//! },
+//! ```
+//!
+//! The first binding expands to a row comparison against the cursor, or to a tautology if there is
+//! none, and the second to the matching `ORDER BY` and `LIMIT` clauses.
+
+use std::collections::HashMap;
+
+use quote::{format_ident, quote, ToTokens};
+use syn::{parse::Parse, parse_quote, spanned::Spanned};
+
+use crate::{
+    parse::{OneOrPunctuated, ParsedConditionalQueryAs},
+    DatabaseType,
+};
+
+syn::custom_keyword!(keyset);
+
+/// A parsed `keyset { ... }` construct.
+#[derive(Clone, Debug)]
+pub(crate) struct Keyset {
+    /// The sort keys, in order of precedence.
+    keys: Vec<Key>,
+    direction: Direction,
+    /// A place expression holding an `Option` of a struct with a field for every key.
+    ///
+    /// It's evaluated once to select the variant and once for every referenced value.
+    cursor: syn::Expr,
+    /// The run-time binding holding the page size, if any.
+    limit: Option<syn::Ident>,
+}
+
+#[derive(Clone, Debug)]
+struct Key {
+    /// The column as it's written in SQL, e.g. `p.created_at`.
+    column: String,
+    /// The field of the cursor holding the key's value, e.g. `created_at`.
+    field: syn::Ident,
+}
+
+#[derive(Clone, Debug)]
+enum Direction {
+    Ascending,
+    Descending,
+    /// An expression evaluating to a `sqlx_conditional_queries::SortDirection`.
+    Dynamic(syn::Expr),
+}
+
+impl Parse for Keyset {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let keyword = input.parse::<keyset>()?;
+        let content;
+        syn::braced!(content in input);
+        let fields = content.parse_terminated(syn::FieldValue::parse, syn::token::Comma)?;
+
+        let mut keys = None;
+        let mut direction = None;
+        let mut cursor = None;
+        let mut limit = None;
+        for field in fields {
+            let syn::Member::Named(name) = &field.member else {
+                return Err(syn::Error::new(
+                    field.member.span(),
+                    "expected a named field",
+                ));
+            };
+            match name.to_string().as_str() {
+                "keys" => keys = Some(parse_keys(&field.expr)?),
+                "direction" => direction = Some(parse_direction(field.expr)),
+                "cursor" => cursor = Some(field.expr),
+                "limit" => match field.expr {
+                    syn::Expr::Path(path) if path.path.get_ident().is_some() => {
+                        limit = path.path.get_ident().cloned();
+                    }
+                    expr => {
+                        return Err(syn::Error::new(
+                            expr.span(),
+                            "expected the name of a run-time binding",
+                        ))
+                    }
+                },
+                _ => {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        "unknown keyset field, expected one of `keys`, `direction`, `cursor`, or `limit`",
+                    ))
+                }
+            }
+        }
+
+        let missing =
+            |field| syn::Error::new(keyword.span, format!("missing keyset field `{field}`"));
+        Ok(Self {
+            keys: keys.ok_or_else(|| missing("keys"))?,
+            direction: direction.ok_or_else(|| missing("direction"))?,
+            cursor: cursor.ok_or_else(|| missing("cursor"))?,
+            limit,
+        })
+    }
+}
+
+fn parse_keys(expr: &syn::Expr) -> syn::Result<Vec<Key>> {
+    let syn::Expr::Array(array) = expr else {
+        return Err(syn::Error::new(
+            expr.span(),
+            "expected an array of sort keys, e.g. `[created_at, id]`",
+        ));
+    };
+    if array.elems.is_empty() {
+        return Err(syn::Error::new(
+            array.span(),
+            "expected at least one sort key",
+        ));
+    }
+
+    array
+        .elems
+        .iter()
+        .map(|elem| {
+            let field = match elem {
+                syn::Expr::Path(path) => path.path.get_ident().cloned(),
+                syn::Expr::Field(syn::ExprField {
+                    member: syn::Member::Named(field),
+                    ..
+                }) => Some(field.clone()),
+                _ => None,
+            };
+            let field = field.ok_or_else(|| {
+                syn::Error::new(elem.span(), "expected a column, e.g. `id` or `p.id`")
+            })?;
+
+            Ok(Key {
+                column: elem
+                    .to_token_stream()
+                    .to_string()
+                    .split_whitespace()
+                    .collect(),
+                field,
+            })
+        })
+        .collect()
+}
+
+fn parse_direction(expr: syn::Expr) -> Direction {
+    if let syn::Expr::Path(path) = &expr {
+        if path.path.is_ident("asc") {
+            return Direction::Ascending;
+        }
+        if path.path.is_ident("desc") {
+            return Direction::Descending;
+        }
+    }
+    Direction::Dynamic(expr)
+}
+
+/// Replaces all keyset constructs of `parsed` with equivalent compile-time bindings.
+///
+/// Returns the expressions of the run-time bindings referencing the values of the cursors.  They
+/// borrow from the cursor itself, as arguments have to outlive the generated `match` statement.
+pub(crate) fn desugar(
+    parsed: &mut ParsedConditionalQueryAs,
+    database_type: DatabaseType,
+) -> HashMap<String, syn::Expr> {
+    let mut arguments = HashMap::new();
+    for (names, keyset) in std::mem::take(&mut parsed.keysets) {
+        let match_expression = keyset.desugar(&names, database_type, &mut arguments);
+        parsed.compile_time_bindings.push((
+            OneOrPunctuated::Punctuated(names, match_expression.span()),
+            match_expression,
+        ));
+    }
+    arguments
+}
+
+impl Keyset {
+    fn desugar(
+        &self,
+        names: &syn::punctuated::Punctuated<syn::Ident, syn::token::Comma>,
+        database_type: DatabaseType,
+        arguments: &mut HashMap<String, syn::Expr>,
+    ) -> syn::ExprMatch {
+        // The values of the cursor are referenced as run-time bindings named after the predicate
+        // binding.  They're only referenced by the arms which matched a cursor, so unwrapping it
+        // never panics.
+        let predicate_name = &names[0];
+        let cursor = &self.cursor;
+        let values: Vec<_> = self
+            .keys
+            .iter()
+            .map(|key| {
+                let name = format_ident!("__{}_{}", predicate_name, key.field);
+                let field = &key.field;
+                arguments.insert(
+                    name.to_string(),
+                    parse_quote!((#cursor).as_ref().unwrap().#field),
+                );
+                name
+            })
+            .collect();
+
+        let directions = match &self.direction {
+            Direction::Ascending => vec![(true, None)],
+            Direction::Descending => vec![(false, None)],
+            Direction::Dynamic(_) => vec![
+                (
+                    true,
+                    Some(quote!(::sqlx_conditional_queries::SortDirection::Ascending)),
+                ),
+                (
+                    false,
+                    Some(quote!(
+                        ::sqlx_conditional_queries::SortDirection::Descending
+                    )),
+                ),
+            ],
+        };
+
+        let mut arms: Vec<syn::Arm> = Vec::new();
+        for (ascending, direction_pattern) in directions {
+            for with_cursor in [true, false] {
+                let pattern = match &direction_pattern {
+                    Some(direction_pattern) => quote!((#direction_pattern, #with_cursor)),
+                    None => quote!(#with_cursor),
+                };
+
+                let label = format!(
+                    "{}_{}",
+                    if ascending { "asc" } else { "desc" },
+                    if with_cursor { "after" } else { "first" },
+                );
+                let predicate = if with_cursor {
+                    self.predicate(&values, ascending, database_type)
+                } else {
+                    "1 = 1".to_string()
+                };
+                let page = self.page(ascending);
+
+                arms.push(parse_quote! {
+                    #[label = #label]
+                    #pattern => (#predicate, #page),
+                });
+            }
+        }
+
+        let expression = match &self.direction {
+            Direction::Dynamic(direction) => quote!((#direction, (#cursor).is_some())),
+            Direction::Ascending | Direction::Descending => quote!((#cursor).is_some()),
+        };
+        parse_quote! {
+            match #expression {
+                #(#arms)*
+            }
+        }
+    }
+
+    /// Returns the predicate selecting the rows following the cursor.
+    fn predicate(
+        &self,
+        values: &[syn::Ident],
+        ascending: bool,
+        database_type: DatabaseType,
+    ) -> String {
+        let operator = if ascending { ">" } else { "<" };
+        let columns: Vec<_> = self.keys.iter().map(|key| key.column.as_str()).collect();
+        let values: Vec<_> = values.iter().map(|value| format!("{{{value}}}")).collect();
+
+        match database_type {
+            DatabaseType::PostgreSql | DatabaseType::Sqlite => format!(
+                "({}) {operator} ({})",
+                columns.join(", "),
+                values.join(", "),
+            ),
+            // MySQL only uses indexes for row comparisons in some cases, so we spell the
+            // comparison out instead.
+            DatabaseType::MySql => {
+                let disjuncts: Vec<_> = (0..columns.len())
+                    .map(|i| {
+                        let mut conjuncts: Vec<_> = (0..i)
+                            .map(|j| format!("{} = {}", columns[j], values[j]))
+                            .collect();
+                        conjuncts.push(format!("{} {operator} {}", columns[i], values[i]));
+                        format!("({})", conjuncts.join(" AND "))
+                    })
+                    .collect();
+                format!("({})", disjuncts.join(" OR "))
+            }
+        }
+    }
+
+    /// Returns the `ORDER BY` and `LIMIT` clauses.
+    fn page(&self, ascending: bool) -> String {
+        let direction = if ascending { "ASC" } else { "DESC" };
+        let order: Vec<_> = self
+            .keys
+            .iter()
+            .map(|key| format!("{} {direction}", key.column))
+            .collect();
+        let mut page = format!("ORDER BY {}", order.join(", "));
+        if let Some(limit) = &self.limit {
+            page.push_str(&format!(" LIMIT {{{limit}}}"));
+        }
+        page
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desugared(database_type: DatabaseType, input: &str) -> ParsedConditionalQueryAs {
+        let mut parsed = syn::parse_str::<ParsedConditionalQueryAs>(input).unwrap();
+        let arguments = desugar(&mut parsed, database_type);
+        assert_eq!(arguments.len(), 2);
+        parsed
+    }
+
+    fn arm_bodies(parsed: &ParsedConditionalQueryAs) -> Vec<String> {
+        parsed.compile_time_bindings[0]
+            .1
+            .arms
+            .iter()
+            .map(|arm| arm.body.to_token_stream().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn dynamic_direction() {
+        let parsed = desugared(
+            DatabaseType::PostgreSql,
+            r#"
+                SomeType,
+                "SELECT * FROM posts WHERE {#after} {#page}",
+                #(after, page) = keyset {
+                    keys: [p.created_at, id],
+                    direction: direction,
+                    cursor: cursor,
+                    limit: page_size,
+                },
+            "#,
+        );
+
+        assert!(parsed.keysets.is_empty());
+        assert_eq!(
+            arm_bodies(&parsed),
+            [
+                r#"("(p.created_at, id) > ({__after_created_at}, {__after_id})" , "ORDER BY p.created_at ASC, id ASC LIMIT {page_size}")"#,
+                r#"("1 = 1" , "ORDER BY p.created_at ASC, id ASC LIMIT {page_size}")"#,
+                r#"("(p.created_at, id) < ({__after_created_at}, {__after_id})" , "ORDER BY p.created_at DESC, id DESC LIMIT {page_size}")"#,
+                r#"("1 = 1" , "ORDER BY p.created_at DESC, id DESC LIMIT {page_size}")"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn static_direction_on_mysql() {
+        let parsed = desugared(
+            DatabaseType::MySql,
+            r#"
+                SomeType,
+                "SELECT * FROM posts WHERE {#after} {#page}",
+                #(after, page) = keyset {
+                    keys: [created_at, id],
+                    direction: desc,
+                    cursor: cursor,
+                },
+            "#,
+        );
+
+        assert_eq!(
+            arm_bodies(&parsed),
+            [
+                r#"("((created_at < {__after_created_at}) OR (created_at = {__after_created_at} AND id < {__after_id}))" , "ORDER BY created_at DESC, id DESC")"#,
+                r#"("1 = 1" , "ORDER BY created_at DESC, id DESC")"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_field() {
+        let error = syn::parse_str::<ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "SELECT * FROM posts WHERE {#after} {#page}",
+                #(after, page) = keyset {
+                    keys: [id],
+                    direction: asc,
+                },
+            "#,
+        )
+        .unwrap_err();
+
+        assert_eq!(error.to_string(), "missing keyset field `cursor`");
+    }
+}
@@ -1,44 +1,196 @@
 #![doc = include_str!("../README.md")]
 
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
 pub use analyze::AnalyzeError;
-pub use expand::ExpandError;
+pub use expand::{ExpandError, QueryLocation, SqlComment};
+pub use lint::Lint;
 
 mod analyze;
 mod codegen;
 mod expand;
+mod keyset;
+mod lint;
 mod lower;
 mod parse;
+mod placeholder;
+mod suggest;
 
 #[cfg(test)]
 mod snapshot_tests;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DatabaseType {
     PostgreSql,
     MySql,
     Sqlite,
 }
 
+impl DatabaseType {
+    /// Returns the database type named by the scheme of a database URL such as `DATABASE_URL`,
+    /// accepting the same schemes as SQLx.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let (scheme, _) = url.split_once(':')?;
+        match scheme {
+            "postgres" | "postgresql" => Some(Self::PostgreSql),
+            "mysql" | "mariadb" => Some(Self::MySql),
+            "sqlite" => Some(Self::Sqlite),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("syn error: {0}")]
     SynError(#[from] syn::Error),
-    #[error("analyze error: {0}")]
-    AnalyzeError(#[from] analyze::AnalyzeError),
-    #[error("expand error: {0}")]
-    ExpandError(#[from] expand::ExpandError),
+    /// All errors found while analyzing the compile-time bindings.
+    #[error("analyze errors: {}", .0.iter().join("; "))]
+    AnalyzeErrors(Vec<analyze::AnalyzeError>),
+    /// All errors found while expanding the query string, reported once even if they occur in
+    /// several variants.
+    #[error("expand errors: {}", .0.iter().join("; "))]
+    ExpandErrors(Vec<expand::ExpandError>),
+    /// The lints found in an invocation passing `unused_bindings = deny`.
+    #[error("denied lints: {}", .0.iter().join("; "))]
+    Lints(Vec<lint::Lint>),
 }
 
+/// Expands a `conditional_query_as!` invocation.
+///
+/// `database_type` returns the database selected by the environment or the enabled features, if
+/// any.  It's only called if the invocation doesn't select one using the `database` option.
+/// Invocations passing `database = any` are expanded for every database in `any_database_types`
+/// instead.
 pub fn conditional_query_as(
-    database_type: DatabaseType,
+    database_type: impl FnOnce() -> Option<DatabaseType>,
+    any_database_types: &[DatabaseType],
     input: proc_macro2::TokenStream,
     checked: bool,
+    sql_comment: Option<&SqlComment>,
+    capture_arguments: bool,
 ) -> Result<proc_macro2::TokenStream, Error> {
     let parsed = syn::parse2::<parse::ParsedConditionalQueryAs>(input)?;
-    let analyzed = analyze::analyze(parsed)?;
+    let database_type = match parsed.database {
+        Some(parse::DatabaseOption::Database(database_type)) => database_type,
+        Some(parse::DatabaseOption::Any) => {
+            return conditional_query_as_any(
+                any_database_types,
+                parsed,
+                sql_comment,
+                capture_arguments,
+            );
+        }
+        None => database_type().ok_or_else(|| {
+            syn::Error::new(
+                proc_macro2::Span::call_site(),
+                "no database selected, enable one of the database features or pass e.g. `database = postgres`",
+            )
+        })?,
+    };
+
+    let expansion = expand_for(database_type, parsed, sql_comment)?;
+    let codegened = codegen::codegen(
+        expansion.expanded,
+        checked,
+        capture_arguments,
+        &expansion.argument_expressions,
+    );
+
+    Ok(codegen::with_warnings(&expansion.lints, codegened))
+}
+
+/// Expands an invocation passing `database = any` for every database in `database_types`.
+fn conditional_query_as_any(
+    database_types: &[DatabaseType],
+    parsed: parse::ParsedConditionalQueryAs,
+    sql_comment: Option<&SqlComment>,
+    capture_arguments: bool,
+) -> Result<proc_macro2::TokenStream, Error> {
+    if database_types.is_empty() {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`database = any` requires the `any` feature and at least one database feature",
+        )
+        .into());
+    }
+
+    // The lints don't depend on the database, so they're the same for every expansion.
+    let mut expansions = Vec::new();
+    let mut argument_expressions = HashMap::new();
+    let mut lints = Vec::new();
+    for &database_type in database_types {
+        let expansion = expand_for(database_type, parsed.clone(), sql_comment)?;
+        expansions.push((database_type, expansion.expanded));
+        argument_expressions = expansion.argument_expressions;
+        lints = expansion.lints;
+    }
+
+    let codegened = codegen::codegen_any(expansions, capture_arguments, &argument_expressions);
+
+    Ok(codegen::with_warnings(&lints, codegened))
+}
+
+/// The output of all steps of the pipeline before code generation.
+struct Expansion {
+    expanded: expand::ExpandedConditionalQueryAs,
+    /// The expressions of synthesized run-time bindings.
+    argument_expressions: HashMap<String, syn::Expr>,
+    /// The lints to report as warnings.
+    lints: Vec<Lint>,
+}
+
+/// Runs all steps of the pipeline before code generation for the given database.
+///
+/// Lints are an error if the invocation passes `unused_bindings = deny`.
+fn expand_for(
+    database_type: DatabaseType,
+    mut parsed: parse::ParsedConditionalQueryAs,
+    sql_comment: Option<&SqlComment>,
+) -> Result<Expansion, Error> {
+    let argument_expressions = keyset::desugar(&mut parsed, database_type);
+    let lint_level = parsed.unused_bindings.unwrap_or_default();
+    let analyzed = analyze::analyze(parsed).map_err(Error::AnalyzeErrors)?;
+
+    let lints = match lint_level {
+        lint::LintLevel::Allow => Vec::new(),
+        lint::LintLevel::Warn => lint::unused_bindings(&analyzed),
+        lint::LintLevel::Deny => {
+            let lints = lint::unused_bindings(&analyzed);
+            if !lints.is_empty() {
+                return Err(Error::Lints(lints));
+            }
+            lints
+        }
+    };
+
     let lowered = lower::lower(analyzed);
-    let expanded = expand::expand(database_type, lowered)?;
-    let codegened = codegen::codegen(expanded, checked);
+    let expanded = expand::expand(database_type, &database_type, lowered, sql_comment)
+        .map_err(Error::ExpandErrors)?;
+
+    Ok(Expansion {
+        expanded,
+        argument_expressions,
+        lints,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(codegened)
+    #[rstest::rstest]
+    #[case("postgres://user@localhost/db", Some(DatabaseType::PostgreSql))]
+    #[case("postgresql://localhost", Some(DatabaseType::PostgreSql))]
+    #[case("mysql://root@localhost/db", Some(DatabaseType::MySql))]
+    #[case("mariadb://localhost", Some(DatabaseType::MySql))]
+    #[case("sqlite:data.db", Some(DatabaseType::Sqlite))]
+    #[case("sqlite::memory:", Some(DatabaseType::Sqlite))]
+    #[case("mssql://localhost", None)]
+    #[case("data.db", None)]
+    fn database_type_from_url(#[case] url: &str, #[case] expected: Option<DatabaseType>) {
+        assert_eq!(DatabaseType::from_url(url), expected);
+    }
 }
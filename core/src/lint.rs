@@ -0,0 +1,223 @@
+use std::collections::HashSet;
+
+use syn::spanned::Spanned;
+
+use crate::analyze::{compile_time_bindings, AnalyzedConditionalQueryAs};
+
+/// A compile-time binding which doesn't contribute to the query, but still multiplies the number
+/// of variants.
+#[derive(Debug, thiserror::Error)]
+pub enum Lint {
+    #[error("unused compile-time binding: {0}")]
+    UnusedBinding(proc_macro2::Ident),
+    #[error("compile-time binding `{name}` is only referenced from bindings which are never used")]
+    UnreachableBinding {
+        name: proc_macro2::Ident,
+        /// The bindings whose values reference this one, none of which is reachable from the
+        /// query string.
+        referenced_from: Vec<String>,
+    },
+}
+
+/// How the `unused_bindings` option reports [`Lint`]s.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum LintLevel {
+    Allow,
+    #[default]
+    Warn,
+    Deny,
+}
+
+impl LintLevel {
+    /// Parses the `unused_bindings = allow | warn | deny` option.
+    pub(crate) fn from_meta(meta: syn::Meta) -> syn::Result<Self> {
+        let syn::Meta::NameValue(syn::MetaNameValue {
+            value: syn::Expr::Path(path),
+            ..
+        }) = &meta
+        else {
+            return Err(syn::Error::new(
+                meta.span(),
+                "expected `unused_bindings = allow`, `unused_bindings = warn` or `unused_bindings = deny`",
+            ));
+        };
+
+        match path.path.get_ident().map(ToString::to_string).as_deref() {
+            Some("allow") => Ok(Self::Allow),
+            Some("warn") => Ok(Self::Warn),
+            Some("deny") => Ok(Self::Deny),
+            _ => Err(syn::Error::new(
+                path.span(),
+                "unknown lint level, expected `allow`, `warn` or `deny`",
+            )),
+        }
+    }
+}
+
+/// Finds the compile-time bindings which are never referenced, and those which are only
+/// referenced from the values of bindings which aren't reachable from the query string themselves,
+/// so that none of their values ever ends up in a query.
+///
+/// All arms of dialect bindings count as selected, since the others are used when compiling for a
+/// different database.
+pub(crate) fn unused_bindings(analyzed: &AnalyzedConditionalQueryAs) -> Vec<Lint> {
+    let graph = compile_time_bindings::reference_graph(analyzed.binding_values());
+
+    let mut query_references = HashSet::new();
+    compile_time_bindings::fill_references(&mut query_references, &analyzed.query_string.value());
+
+    // Walk the graph starting from the references in the query string.
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<&str> = query_references.iter().map(String::as_str).collect();
+    while let Some(name) = pending.pop() {
+        if !reachable.insert(name) {
+            continue;
+        }
+        if let Some((_, references)) = graph.get(name) {
+            pending.extend(references.iter().map(String::as_str));
+        }
+    }
+
+    // The graph doesn't preserve the declaration order, which the lints should be reported in.
+    let mut seen = HashSet::new();
+    let mut lints = Vec::new();
+    for (ident, _) in analyzed.binding_values() {
+        let name = ident.to_string();
+        if reachable.contains(name.as_str()) || !seen.insert(name.clone()) {
+            continue;
+        }
+
+        let mut referenced_from: Vec<_> = graph
+            .iter()
+            .filter(|(_, (_, references))| references.contains(&name))
+            .map(|(referencing, _)| referencing.clone())
+            .collect();
+        referenced_from.sort();
+
+        if referenced_from.is_empty() {
+            lints.push(Lint::UnusedBinding(ident.clone()));
+        } else {
+            lints.push(Lint::UnreachableBinding {
+                name: ident.clone(),
+                referenced_from,
+            });
+        }
+    }
+
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::ParsedConditionalQueryAs;
+
+    fn lint(input: &str) -> Vec<Lint> {
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(input).unwrap();
+        let analyzed = crate::analyze::analyze(parsed).unwrap();
+        unused_bindings(&analyzed)
+    }
+
+    #[test]
+    fn finds_no_lints_in_used_bindings() {
+        let lints = lint(
+            r##"
+                SomeType,
+                "SELECT * FROM users {#filter} {#now}",
+                #filter = match filter {
+                    true => "WHERE {#column} = 1",
+                    false => "",
+                },
+                #column = match column {
+                    _ => "id",
+                },
+                #now = dialect {
+                    postgres => "{#pg_now}",
+                    _ => "CURRENT_TIMESTAMP",
+                },
+                #pg_now = match precise {
+                    true => "clock_timestamp()",
+                    false => "NOW()",
+                },
+            "##,
+        );
+
+        assert!(lints.is_empty(), "unexpected lints: {lints:?}");
+    }
+
+    #[test]
+    fn finds_unused_bindings() {
+        let lints = lint(
+            r##"
+                SomeType,
+                "SELECT * FROM users {#b}",
+                #(a, b) = match foo {
+                    _ => ("1", "2"),
+                },
+                #c = match bar {
+                    _ => "3",
+                },
+            "##,
+        );
+
+        assert!(
+            matches!(
+                lints.as_slice(),
+                [Lint::UnusedBinding(a), Lint::UnusedBinding(c)] if a == "a" && c == "c"
+            ),
+            "unexpected lints: {lints:?}"
+        );
+    }
+
+    #[test]
+    fn finds_unreachable_bindings() {
+        let lints = lint(
+            r##"
+                SomeType,
+                "SELECT * FROM users",
+                #a = match foo {
+                    true => "{#b}",
+                    false => "",
+                },
+                #b = match bar {
+                    _ => "{#c} {#b_suffix}",
+                },
+                #c = match baz {
+                    _ => "3",
+                },
+                #b_suffix = match qux {
+                    _ => "{#c}",
+                },
+            "##,
+        );
+
+        assert!(
+            matches!(
+                lints.as_slice(),
+                [
+                    Lint::UnusedBinding(a),
+                    Lint::UnreachableBinding { name: b, referenced_from: b_from },
+                    Lint::UnreachableBinding { name: c, referenced_from: c_from },
+                    Lint::UnreachableBinding { name: b_suffix, .. },
+                ] if a == "a"
+                    && b == "b"
+                    && b_from == &["a"]
+                    && c == "c"
+                    && c_from == &["b", "b_suffix"]
+                    && b_suffix == "b_suffix"
+            ),
+            "unexpected lints: {lints:?}"
+        );
+    }
+
+    #[rstest::rstest]
+    #[case("unused_bindings = allow", Some(LintLevel::Allow))]
+    #[case("unused_bindings = warn", Some(LintLevel::Warn))]
+    #[case("unused_bindings = deny", Some(LintLevel::Deny))]
+    #[case("unused_bindings = forbid", None)]
+    #[case("unused_bindings(deny)", None)]
+    fn lint_levels(#[case] option: &str, #[case] expected: Option<LintLevel>) {
+        let meta = syn::parse_str::<syn::Meta>(option).unwrap();
+        assert_eq!(LintLevel::from_meta(meta).ok(), expected);
+    }
+}
@@ -3,7 +3,10 @@ use std::collections::HashMap;
 use itertools::Itertools;
 use syn::parse_quote;
 
-use crate::analyze::AnalyzedConditionalQueryAs;
+use crate::{
+    analyze::{AnalyzedConditionalQueryAs, DialectBinding},
+    parse::CountOption,
+};
 
 #[derive(Debug)]
 pub(crate) struct LoweredConditionalQueryAs {
@@ -13,11 +16,16 @@ pub(crate) struct LoweredConditionalQueryAs {
     /// These expressions are in the same order as the patterns in the `match_arms` field.
     pub(crate) match_expressions: Vec<syn::Expr>,
     pub(crate) match_arms: Vec<MatchArm>,
+    /// Dialect bindings don't multiply the variants, and are resolved for all arms at once.
+    pub(crate) dialect_bindings: Vec<DialectBinding>,
+    pub(crate) count: Option<CountOption>,
 }
 
 #[derive(Debug)]
 pub(crate) struct MatchArm {
     pub(crate) patterns: Vec<syn::Pat>,
+    /// The labels of the chosen arm of each binding, joined by commas.
+    pub(crate) label: String,
     pub(crate) compile_time_bindings: HashMap<String, syn::LitStr>,
 }
 
@@ -89,13 +97,20 @@ pub(crate) fn lower(analyzed: AnalyzedConditionalQueryAs) -> LoweredConditionalQ
         }
 
         let mut guards = Vec::new();
+        let mut labels = Vec::new();
         let mut bindings = HashMap::new();
-        binding.into_iter().for_each(|(g, b)| {
-            guards.push(g);
-            bindings.extend(b.into_iter().map(|(name, value)| (name.to_string(), value)));
+        binding.into_iter().for_each(|arm| {
+            guards.push(arm.pattern);
+            labels.push(arm.label);
+            bindings.extend(
+                arm.values
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value)),
+            );
         });
         match_arms.push(MatchArm {
             patterns: guards,
+            label: labels.join(","),
             compile_time_bindings: bindings,
         });
     }
@@ -107,6 +122,7 @@ pub(crate) fn lower(analyzed: AnalyzedConditionalQueryAs) -> LoweredConditionalQ
         match_expressions.push(parse_quote!(()));
         match_arms.push(crate::lower::MatchArm {
             patterns: vec![parse_quote!(())],
+            label: String::new(),
             compile_time_bindings: HashMap::new(),
         });
     }
@@ -116,6 +132,8 @@ pub(crate) fn lower(analyzed: AnalyzedConditionalQueryAs) -> LoweredConditionalQ
         query_string: analyzed.query_string,
         match_expressions,
         match_arms,
+        dialect_bindings: analyzed.dialect_bindings,
+        count: analyzed.count,
     }
 }
 
@@ -141,6 +159,15 @@ mod tests {
         )
         .unwrap();
         let analyzed = crate::analyze::analyze(parsed.clone()).unwrap();
-        let _lowered = lower(analyzed);
+        let lowered = lower(analyzed);
+
+        assert_eq!(
+            lowered
+                .match_arms
+                .iter()
+                .map(|arm| arm.label.as_str())
+                .collect::<Vec<_>>(),
+            &["d,k", "d,m", "g,k", "g,m"],
+        );
     }
 }
@@ -1,4 +1,10 @@
-use syn::{parenthesized, parse::Parse};
+use syn::{bracketed, parenthesized, parse::Parse, spanned::Spanned};
+
+use crate::{
+    keyset::{self, Keyset},
+    lint::LintLevel,
+    DatabaseType,
+};
 
 #[derive(Clone, Debug)]
 pub(crate) struct ParsedConditionalQueryAs {
@@ -11,6 +17,130 @@ pub(crate) struct ParsedConditionalQueryAs {
         OneOrPunctuated<syn::Ident, syn::token::Comma>,
         syn::ExprMatch,
     )>,
+    /// All dialect bindings, each with its variables and the arms for the different databases.
+    pub(crate) dialect_bindings: Vec<(
+        OneOrPunctuated<syn::Ident, syn::token::Comma>,
+        DialectBinding,
+    )>,
+    /// All keyset pagination bindings, which are desugared into compile-time bindings before
+    /// analysis.
+    pub(crate) keysets: Vec<(
+        syn::punctuated::Punctuated<syn::Ident, syn::token::Comma>,
+        Keyset,
+    )>,
+    /// The `count` option, if it was given.
+    pub(crate) count: Option<CountOption>,
+    /// The database given by the `database` option, which overrides the one selected by the
+    /// enabled features.
+    pub(crate) database: Option<DatabaseOption>,
+    /// The level given by the `unused_bindings` option, which controls how unused compile-time
+    /// bindings are reported.
+    pub(crate) unused_bindings: Option<LintLevel>,
+}
+
+syn::custom_keyword!(dialect);
+
+/// A `dialect { postgres => "...", ... }` binding, whose values are chosen by the database the
+/// query is expanded for rather than at run time.
+///
+/// Its arms are written like match arms whose patterns name databases, see
+/// [`crate::analyze::DialectArm`].
+#[derive(Clone, Debug)]
+pub(crate) struct DialectBinding {
+    pub(crate) keyword: dialect,
+    pub(crate) arms: Vec<syn::Arm>,
+}
+
+impl Parse for DialectBinding {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let keyword = input.parse::<dialect>()?;
+        let content;
+        syn::braced!(content in input);
+        let mut arms = Vec::new();
+        while !content.is_empty() {
+            arms.push(content.parse::<syn::Arm>()?);
+        }
+
+        Ok(Self { keyword, arms })
+    }
+}
+
+/// The `count` option, which generates companion `COUNT(*)` and `EXISTS` queries.
+///
+/// It's given either as `count`, or as `count(omit = [#limit, ...])` to replace the listed
+/// compile-time bindings with empty strings in the companion queries, e.g. to drop pagination.
+#[derive(Clone, Debug)]
+pub(crate) struct CountOption {
+    pub(crate) omit: Vec<syn::Ident>,
+}
+
+impl CountOption {
+    fn from_meta(meta: syn::Meta) -> syn::Result<Self> {
+        let mut omit = Vec::new();
+        match meta {
+            syn::Meta::Path(_) => {}
+            syn::Meta::List(list) => list.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("omit") {
+                    return Err(meta.error("expected `omit`"));
+                }
+
+                let value = meta.value()?;
+                let content;
+                bracketed!(content in value);
+                while !content.is_empty() {
+                    content.parse::<syn::token::Pound>()?;
+                    omit.push(content.parse::<syn::Ident>()?);
+                    if content.is_empty() {
+                        break;
+                    }
+                    content.parse::<syn::token::Comma>()?;
+                }
+                Ok(())
+            })?,
+            syn::Meta::NameValue(meta) => {
+                return Err(syn::Error::new(
+                    meta.span(),
+                    "expected `count` or `count(omit = [...])`",
+                ))
+            }
+        }
+
+        Ok(Self { omit })
+    }
+}
+
+/// The value of the `database` option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DatabaseOption {
+    /// Expand the query for the given database.
+    Database(DatabaseType),
+    /// Expand the query for every enabled database, for use with `sqlx::Any`.
+    Any,
+}
+
+/// Parses the `database = postgres | mysql | sqlite | any` option.
+fn database_from_meta(meta: syn::Meta) -> syn::Result<DatabaseOption> {
+    let syn::Meta::NameValue(syn::MetaNameValue {
+        value: syn::Expr::Path(path),
+        ..
+    }) = &meta
+    else {
+        return Err(syn::Error::new(
+            meta.span(),
+            "expected `database = postgres`, `database = mysql`, `database = sqlite` or `database = any`",
+        ));
+    };
+
+    match path.path.get_ident().map(ToString::to_string).as_deref() {
+        Some("postgres") => Ok(DatabaseOption::Database(DatabaseType::PostgreSql)),
+        Some("mysql") => Ok(DatabaseOption::Database(DatabaseType::MySql)),
+        Some("sqlite") => Ok(DatabaseOption::Database(DatabaseType::Sqlite)),
+        Some("any") => Ok(DatabaseOption::Any),
+        _ => Err(syn::Error::new(
+            path.span(),
+            "unknown database, expected `postgres`, `mysql`, `sqlite` or `any`",
+        )),
+    }
 }
 
 /// This enum represents the identifier (`#foo`, `#(foo, bar)`) of single binding expression
@@ -64,8 +194,13 @@ impl Parse for ParsedConditionalQueryAs {
         let query_string = input.parse::<syn::LitStr>()?;
 
         // The rest of the input has to be an optional sequence of compile-time binding
-        // expressions.
+        // expressions and options.
         let mut compile_time_bindings = Vec::new();
+        let mut dialect_bindings = Vec::new();
+        let mut keysets = Vec::new();
+        let mut count = None;
+        let mut database = None;
+        let mut unused_bindings = None;
         while !input.is_empty() {
             // Every binding expression has to be preceded by a comma, and we also allow the final
             // comma to be optional.
@@ -74,6 +209,33 @@ impl Parse for ParsedConditionalQueryAs {
                 break;
             }
 
+            // Options are the only entries which don't start with a #.
+            if !input.peek(syn::token::Pound) {
+                let meta = input.parse::<syn::Meta>()?;
+                if meta.path().is_ident("count") {
+                    if count.is_some() {
+                        return Err(syn::Error::new(meta.span(), "duplicate `count` option"));
+                    }
+                    count = Some(CountOption::from_meta(meta)?);
+                } else if meta.path().is_ident("database") {
+                    if database.is_some() {
+                        return Err(syn::Error::new(meta.span(), "duplicate `database` option"));
+                    }
+                    database = Some(database_from_meta(meta)?);
+                } else if meta.path().is_ident("unused_bindings") {
+                    if unused_bindings.is_some() {
+                        return Err(syn::Error::new(
+                            meta.span(),
+                            "duplicate `unused_bindings` option",
+                        ));
+                    }
+                    unused_bindings = Some(LintLevel::from_meta(meta)?);
+                } else {
+                    return Err(syn::Error::new(meta.path().span(), "unknown option"));
+                }
+                continue;
+            }
+
             // Every binding expression starts with a #.
             input.parse::<syn::token::Pound>()?;
 
@@ -95,6 +257,28 @@ impl Parse for ParsedConditionalQueryAs {
             // Binding names and match is delimited by equals sign.
             input.parse::<syn::token::Eq>()?;
 
+            if input.peek(dialect) {
+                dialect_bindings.push((binding_names, input.parse::<DialectBinding>()?));
+                continue;
+            }
+
+            // Keyset pagination binds exactly two names, the predicate and the page clauses.
+            if input.peek(keyset::keyset) {
+                let keyset = input.parse::<Keyset>()?;
+                match binding_names {
+                    OneOrPunctuated::Punctuated(names, _) if names.len() == 2 => {
+                        keysets.push((names, keyset));
+                    }
+                    names => {
+                        return Err(syn::Error::new(
+                            names.span(),
+                            "keyset pagination binds two names, e.g. `#(after, page)`",
+                        ))
+                    }
+                }
+                continue;
+            }
+
             // And finally we parse a match expression.
             let match_expression = input.parse::<syn::ExprMatch>()?;
 
@@ -105,6 +289,11 @@ impl Parse for ParsedConditionalQueryAs {
             output_type,
             query_string,
             compile_time_bindings,
+            dialect_bindings,
+            keysets,
+            count,
+            database,
+            unused_bindings,
         })
     }
 }
@@ -162,4 +351,77 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn count_option() {
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(
+            r#"
+                SomeType,
+                "some SQL query",
+                #binding = match foo {
+                    bar => "baz",
+                },
+                count(omit = [#binding]),
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            parsed.count.unwrap().omit,
+            [syn::Ident::new("binding", proc_macro2::Span::call_site())]
+        );
+
+        let parsed =
+            syn::parse_str::<ParsedConditionalQueryAs>(r#"SomeType, "some SQL query", count"#)
+                .unwrap();
+        assert!(parsed.count.unwrap().omit.is_empty());
+
+        assert!(syn::parse_str::<ParsedConditionalQueryAs>(
+            r#"SomeType, "some SQL query", counts"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn database_option() {
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(
+            r#"SomeType, "some SQL query", database = sqlite"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parsed.database,
+            Some(DatabaseOption::Database(DatabaseType::Sqlite))
+        );
+
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(
+            r#"SomeType, "some SQL query", database = any"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.database, Some(DatabaseOption::Any));
+
+        for input in [
+            r#"SomeType, "some SQL query", database = oracle"#,
+            r#"SomeType, "some SQL query", database(sqlite)"#,
+            r#"SomeType, "some SQL query", database = sqlite, database = mysql"#,
+        ] {
+            assert!(syn::parse_str::<ParsedConditionalQueryAs>(input).is_err());
+        }
+    }
+
+    #[test]
+    fn unused_bindings_option() {
+        let parsed = syn::parse_str::<ParsedConditionalQueryAs>(
+            r#"SomeType, "some SQL query", unused_bindings = deny"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.unused_bindings, Some(LintLevel::Deny));
+
+        let parsed =
+            syn::parse_str::<ParsedConditionalQueryAs>(r#"SomeType, "some SQL query""#).unwrap();
+        assert_eq!(parsed.unused_bindings, None);
+
+        assert!(syn::parse_str::<ParsedConditionalQueryAs>(
+            r#"SomeType, "some SQL query", unused_bindings = deny, unused_bindings = allow"#,
+        )
+        .is_err());
+    }
 }
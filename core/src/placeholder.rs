@@ -0,0 +1,51 @@
+use crate::DatabaseType;
+
+/// Describes how a database refers to run-time bound parameters in SQL.
+///
+/// This is implemented by [`DatabaseType`] for the databases supported by SQLx itself.
+pub(crate) trait PlaceholderStrategy {
+    /// Returns the placeholder referencing the argument at the 1-based `index`.
+    fn placeholder(&self, index: usize) -> String;
+
+    /// Whether all references to a binding share a single argument.
+    ///
+    /// If this is `false`, the binding is passed again for every reference, and `index` counts
+    /// references instead of distinct bindings.
+    fn reuses_arguments(&self) -> bool;
+
+    /// Returns `placeholder` cast to `sql_type` in SQL, as written using `{name::sql_type}`.
+    ///
+    /// Defaults to the standard `CAST(placeholder AS sql_type)`.
+    fn cast(&self, placeholder: &str, sql_type: &str) -> String {
+        format!("CAST({placeholder} AS {sql_type})")
+    }
+}
+
+impl PlaceholderStrategy for DatabaseType {
+    /// PostgreSQL and SQLite use 1-indexed references such as `$1` and `?1`, while MySQL only
+    /// supports `?`.
+    fn placeholder(&self, index: usize) -> String {
+        match self {
+            DatabaseType::PostgreSql => format!("${index}"),
+            DatabaseType::Sqlite => format!("?{index}"),
+            DatabaseType::MySql => "?".to_string(),
+        }
+    }
+
+    fn reuses_arguments(&self) -> bool {
+        match self {
+            DatabaseType::PostgreSql | DatabaseType::Sqlite => true,
+            DatabaseType::MySql => false,
+        }
+    }
+
+    /// PostgreSQL uses its `$1::type` shorthand.
+    fn cast(&self, placeholder: &str, sql_type: &str) -> String {
+        match self {
+            DatabaseType::PostgreSql => format!("{placeholder}::{sql_type}"),
+            DatabaseType::MySql | DatabaseType::Sqlite => {
+                format!("CAST({placeholder} AS {sql_type})")
+            }
+        }
+    }
+}
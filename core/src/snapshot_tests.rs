@@ -43,7 +43,9 @@ fn only_runtime_bound_parameters(#[case] database_type: DatabaseType, #[case] ch
             WHERE created_at > {created_at}
         "#,
     };
-    let output = crate::conditional_query_as(database_type, input, checked).unwrap();
+    let output =
+        crate::conditional_query_as(|| Some(database_type), &[], input, checked, None, false)
+            .unwrap();
     insta::assert_snapshot!(prettyprint(output));
 }
 
@@ -72,7 +74,9 @@ fn only_compile_time_bound_parameters(#[case] database_type: DatabaseType, #[cas
             _ => "value",
         },
     };
-    let output = crate::conditional_query_as(database_type, input, checked).unwrap();
+    let output =
+        crate::conditional_query_as(|| Some(database_type), &[], input, checked, None, false)
+            .unwrap();
     insta::assert_snapshot!(prettyprint(output));
 }
 
@@ -103,6 +107,217 @@ fn both_parameter_kinds(#[case] database_type: DatabaseType, #[case] checked: bo
             _ => "value",
         },
     };
-    let output = crate::conditional_query_as(database_type, input, checked).unwrap();
+    let output =
+        crate::conditional_query_as(|| Some(database_type), &[], input, checked, None, false)
+            .unwrap();
+    insta::assert_snapshot!(prettyprint(output));
+}
+
+#[rstest::rstest]
+#[case::postgres(DatabaseType::PostgreSql, true)]
+#[case::postgres_unchecked(DatabaseType::PostgreSql, false)]
+#[case::mysql(DatabaseType::MySql, true)]
+#[case::mysql_unchecked(DatabaseType::MySql, false)]
+#[case::sqlite(DatabaseType::Sqlite, true)]
+#[case::sqlite_unchecked(DatabaseType::Sqlite, false)]
+fn count_companions(#[case] database_type: DatabaseType, #[case] checked: bool) {
+    set_snapshot_suffix!(
+        "{:?}{}",
+        database_type,
+        if checked { "" } else { "_unchecked" }
+    );
+    let hash = proc_macro2::Punct::new('#', proc_macro2::Spacing::Alone);
+    let input = quote::quote! {
+        OutputType,
+        r#"
+            SELECT column
+            FROM table
+            WHERE created_at > {created_at}
+            {#page}
+        "#,
+        #hash page = match page {
+            Some(_) => "LIMIT {page}",
+            None => "",
+        },
+        count(omit = [#hash page]),
+    };
+    let output =
+        crate::conditional_query_as(|| Some(database_type), &[], input, checked, None, false)
+            .unwrap();
+    insta::assert_snapshot!(prettyprint(output));
+}
+
+#[rstest::rstest]
+#[case::postgres(DatabaseType::PostgreSql)]
+#[case::mysql(DatabaseType::MySql)]
+#[case::sqlite(DatabaseType::Sqlite)]
+fn reused_run_time_bindings(#[case] database_type: DatabaseType) {
+    set_snapshot_suffix!("{:?}", database_type);
+    let input = quote::quote! {
+        OutputType,
+        r#"
+            SELECT id
+            FROM events
+            WHERE starts_at < {until} AND ends_at > {since}
+               OR starts_at BETWEEN {since} AND {until}
+               OR ends_at BETWEEN {since} AND {until}
+        "#,
+    };
+    let output =
+        crate::conditional_query_as(|| Some(database_type), &[], input, true, None, false).unwrap();
+    insta::assert_snapshot!(prettyprint(output));
+}
+
+#[rstest::rstest]
+#[case::postgres(DatabaseType::PostgreSql)]
+#[case::mysql(DatabaseType::MySql)]
+#[case::sqlite(DatabaseType::Sqlite)]
+fn sql_casts(#[case] database_type: DatabaseType) {
+    set_snapshot_suffix!("{:?}", database_type);
+    let input = quote::quote! {
+        OutputType,
+        "SELECT id FROM events WHERE starts_at > {since::timestamptz} AND kind = {kind::text:_}",
+    };
+    let output =
+        crate::conditional_query_as(|| Some(database_type), &[], input, true, None, false).unwrap();
+    insta::assert_snapshot!(prettyprint(output));
+}
+
+#[rstest::rstest]
+#[case::postgres(DatabaseType::PostgreSql)]
+#[case::mysql(DatabaseType::MySql)]
+#[case::sqlite(DatabaseType::Sqlite)]
+fn keyset_pagination(#[case] database_type: DatabaseType) {
+    set_snapshot_suffix!("{:?}", database_type);
+    let hash = proc_macro2::Punct::new('#', proc_macro2::Spacing::Alone);
+    let input = quote::quote! {
+        OutputType,
+        r#"
+            SELECT id, created_at
+            FROM posts
+            WHERE {#after}
+            {#page}
+        "#,
+        #hash(after, page) = keyset {
+            keys: [created_at, id],
+            direction: direction,
+            cursor: cursor,
+            limit: page_size,
+        },
+    };
+    let output =
+        crate::conditional_query_as(|| Some(database_type), &[], input, true, None, false).unwrap();
+    insta::assert_snapshot!(prettyprint(output));
+}
+
+#[rstest::rstest]
+#[case::postgres(DatabaseType::PostgreSql)]
+#[case::mysql(DatabaseType::MySql)]
+#[case::sqlite(DatabaseType::Sqlite)]
+fn dialect_bindings(#[case] database_type: DatabaseType) {
+    set_snapshot_suffix!("{:?}", database_type);
+    let hash = proc_macro2::Punct::new('#', proc_macro2::Spacing::Alone);
+    let input = quote::quote! {
+        OutputType,
+        r#"
+            SELECT {#now} AS now
+            FROM users
+            WHERE name {#like} {pattern}
+            {#order}
+        "#,
+        #hash now = dialect {
+            postgres | mysql => "NOW()",
+            sqlite => "CURRENT_TIMESTAMP",
+        },
+        #hash like = dialect {
+            postgres => "ILIKE",
+            _ => "LIKE",
+        },
+        #hash order = match descending {
+            true => "ORDER BY name DESC",
+            false => "",
+        },
+    };
+    let output =
+        crate::conditional_query_as(|| Some(database_type), &[], input, true, None, false).unwrap();
+    insta::assert_snapshot!(prettyprint(output));
+}
+
+#[test]
+fn database_option() {
+    let input = quote::quote! {
+        OutputType,
+        "SELECT column FROM table WHERE created_at > {created_at} AND {created_at} < {now}",
+        database = sqlite,
+    };
+    // The default database isn't looked up for invocations selecting one.
+    let output = crate::conditional_query_as(
+        || unreachable!("the default database was looked up"),
+        &[],
+        input,
+        true,
+        None,
+        false,
+    )
+    .unwrap();
+    insta::assert_snapshot!(prettyprint(output));
+
+    let input = quote::quote! {
+        OutputType,
+        "SELECT column FROM table",
+    };
+    let error = crate::conditional_query_as(|| None, &[], input, true, None, false).unwrap_err();
+    assert!(error.to_string().contains("no database selected"));
+}
+
+#[test]
+fn any_database() {
+    let hash = proc_macro2::Punct::new('#', proc_macro2::Spacing::Alone);
+    let input = quote::quote! {
+        OutputType,
+        "SELECT column FROM table WHERE created_at > {created_at} AND {created_at} < {now} {#order}",
+        #hash order = match descending {
+            true => "ORDER BY created_at DESC",
+            false => "",
+        },
+        database = any,
+    };
+    let output = crate::conditional_query_as(
+        || unreachable!("the default database was looked up"),
+        &[DatabaseType::PostgreSql, DatabaseType::Sqlite],
+        input.clone(),
+        true,
+        None,
+        false,
+    )
+    .unwrap();
+    insta::assert_snapshot!(prettyprint(output));
+
+    let error = crate::conditional_query_as(|| None, &[], input, true, None, false).unwrap_err();
+    assert!(error.to_string().contains("requires the `any` feature"));
+}
+
+#[test]
+fn any_database_count_companions() {
+    let hash = proc_macro2::Punct::new('#', proc_macro2::Spacing::Alone);
+    let input = quote::quote! {
+        OutputType,
+        "SELECT column FROM table WHERE created_at > {created_at} {#page}",
+        #hash page = match page {
+            Some(_) => "LIMIT {limit}",
+            None => "",
+        },
+        database = any,
+        count(omit = [#hash page]),
+    };
+    let output = crate::conditional_query_as(
+        || None,
+        &[DatabaseType::MySql, DatabaseType::Sqlite],
+        input,
+        true,
+        None,
+        false,
+    )
+    .unwrap();
     insta::assert_snapshot!(prettyprint(output));
 }
@@ -0,0 +1,81 @@
+//! "Did you mean" suggestions for misspelled binding names.
+
+/// Returns the candidate closest to `name` by edit distance, if any is close enough to plausibly be
+/// a misspelling of it.
+///
+/// Like rustc, we consider candidates within a third of the length of `name` close enough.  Ties
+/// are broken alphabetically, so the suggestion doesn't depend on the order of `candidates`.
+pub(crate) fn closest_match<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Returns the edit distance between `a` and `b` counted in characters, where swapping two
+/// adjacent characters counts as a single edit, since it's a common typo.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // `distances[i][j]` is the distance between the first `i` characters of `a` and the first `j`
+    // characters of `b`.
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = substitution
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case("", "", 0)]
+    #[case("order", "order", 0)]
+    #[case("ordr", "order", 1)]
+    #[case("oredr", "order", 1)]
+    #[case("odrre", "order", 2)]
+    #[case("limit", "", 5)]
+    #[case("kitten", "sitting", 3)]
+    fn edit_distances(#[case] a: &str, #[case] b: &str, #[case] expected: usize) {
+        assert_eq!(edit_distance(a, b), expected);
+        assert_eq!(edit_distance(b, a), expected);
+    }
+
+    #[rstest::rstest]
+    #[case("ordr", Some("order"))]
+    #[case("fliter", Some("filter"))]
+    #[case("ordre", Some("order"))]
+    #[case("lmit", Some("limit"))]
+    #[case("ab", Some("a"))]
+    #[case("direction", None)]
+    fn closest_matches(#[case] name: &str, #[case] expected: Option<&str>) {
+        let candidates = ["a", "filter", "limit", "order"];
+        assert_eq!(closest_match(name, candidates), expected);
+    }
+}
@@ -18,5 +18,13 @@ fn regression_test_(#[case] database_type: DatabaseType, #[case] checked: bool)
     .parse()
     .unwrap();
 
-    sqlx_conditional_queries_core::conditional_query_as(database_type, input, checked).unwrap();
+    sqlx_conditional_queries_core::conditional_query_as(
+        || Some(database_type),
+        &[],
+        input,
+        checked,
+        None,
+        false,
+    )
+    .unwrap();
 }
@@ -1,27 +1,151 @@
 #![doc = include_str!("../README.md")]
 
-use proc_macro_error2::abort;
-use sqlx_conditional_queries_core::{AnalyzeError, DatabaseType, Error, ExpandError};
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+use proc_macro_error2::{abort, emit_error};
+use sqlx_conditional_queries_core::{
+    AnalyzeError, DatabaseType, Error, ExpandError, Lint, QueryLocation, SqlComment,
+};
 
-const DATABASE_TYPE: DatabaseType = if cfg!(feature = "postgres") {
-    DatabaseType::PostgreSql
+/// The database selected by the enabled features, used if `DATABASE_URL` isn't set.
+const DATABASE_TYPE: Option<DatabaseType> = if cfg!(feature = "postgres") {
+    Some(DatabaseType::PostgreSql)
 } else if cfg!(feature = "mysql") {
-    DatabaseType::MySql
+    Some(DatabaseType::MySql)
 } else if cfg!(feature = "sqlite") {
-    DatabaseType::Sqlite
+    Some(DatabaseType::Sqlite)
 } else {
-    panic!("No database feature was enabled")
+    None
 };
 
+/// The databases invocations passing `database = any` are expanded for.
+#[cfg(feature = "any")]
+const ANY_DATABASE_TYPES: &[DatabaseType] = &[
+    #[cfg(feature = "postgres")]
+    DatabaseType::PostgreSql,
+    #[cfg(feature = "mysql")]
+    DatabaseType::MySql,
+    #[cfg(feature = "sqlite")]
+    DatabaseType::Sqlite,
+];
+#[cfg(not(feature = "any"))]
+const ANY_DATABASE_TYPES: &[DatabaseType] = &[];
+
+/// Returns the database used by invocations which don't select one using the `database` option.
+///
+/// This is only called for such invocations, so a bad `DATABASE_URL` doesn't affect the others.
+///
+/// Like SQLx we look for `DATABASE_URL` in the environment and in `.env` files, and choose the
+/// database named by its scheme.  Only if it isn't set do we fall back to the enabled features.
+fn database_type() -> Option<DatabaseType> {
+    let Some(url) = database_url() else {
+        return DATABASE_TYPE;
+    };
+
+    let Some(database_type) = DatabaseType::from_url(&url) else {
+        abort!(
+            proc_macro2::Span::call_site(),
+            "unsupported database in `DATABASE_URL`";
+            help = "only `postgres://`, `mysql://` and `sqlite:` URLs are supported";
+        );
+    };
+
+    let (enabled, feature) = match database_type {
+        DatabaseType::PostgreSql => (cfg!(feature = "postgres"), "postgres"),
+        DatabaseType::MySql => (cfg!(feature = "mysql"), "mysql"),
+        DatabaseType::Sqlite => (cfg!(feature = "sqlite"), "sqlite"),
+    };
+    if !enabled {
+        abort!(
+            proc_macro2::Span::call_site(),
+            "`DATABASE_URL` names a {:?} database, but the `{}` feature is disabled", database_type, feature;
+            help = "enable the `{}` feature of sqlx-conditional-queries", feature;
+        );
+    }
+
+    Some(database_type)
+}
+
+/// The `DATABASE_URL` found in `.env` files, by the manifest directory of the crate it was looked
+/// up for, so the files are read only once per crate.
+static DOTENV_URLS: Mutex<BTreeMap<Option<OsString>, Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// Returns `DATABASE_URL` from the environment, or from `.env` in the crate's manifest directory
+/// or any of the parents of the working directory, which is where SQLx looks for it too.
+fn database_url() -> Option<String> {
+    if let Ok(url) = std::env::var("DATABASE_URL") {
+        return Some(url);
+    }
+
+    let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR");
+    let mut dotenv_urls = DOTENV_URLS.lock().unwrap_or_else(PoisonError::into_inner);
+    dotenv_urls
+        .entry(manifest_dir)
+        .or_insert_with_key(|manifest_dir| dotenv_url(manifest_dir.as_deref()))
+        .clone()
+}
+
+/// Returns `DATABASE_URL` from `.env` in `manifest_dir` or any of the parents of the working
+/// directory.
+fn dotenv_url(manifest_dir: Option<&OsStr>) -> Option<String> {
+    let manifest_env = manifest_dir
+        .map(|dir| Path::new(dir).join(".env"))
+        .filter(|path| path.exists());
+    let items = match manifest_env {
+        Some(path) => dotenvy::from_path_iter(path).ok()?,
+        None => dotenvy::dotenv_iter().ok()?,
+    };
+    items
+        .filter_map(Result::ok)
+        .find(|(key, _)| key == "DATABASE_URL")
+        .map(|(_, value)| value)
+}
+
+/// The environment variable overriding the format of the comment appended by the `sqlcommenter`
+/// feature.
+const SQL_COMMENT_FORMAT_VAR: &str = "SQLX_CONDITIONAL_QUERIES_COMMENT_FORMAT";
+
+/// Returns the comment to append to every variant's SQL if the `sqlcommenter` feature is enabled.
+fn sql_comment() -> Option<SqlComment> {
+    if !cfg!(feature = "sqlcommenter") {
+        return None;
+    }
+
+    let span = proc_macro::Span::call_site();
+    Some(SqlComment {
+        format: std::env::var(SQL_COMMENT_FORMAT_VAR)
+            .unwrap_or_else(|_| SqlComment::DEFAULT_FORMAT.to_string()),
+        file: span.file(),
+        line: span.line(),
+    })
+}
+
+/// Returns a note identifying the binding reference an error is about within its string literal.
+///
+/// On stable the error can only point at the whole string literal, since subspans of literals
+/// require https://github.com/rust-lang/rust/issues/54725, so this is what identifies the
+/// reference.
+fn location_note(location: &QueryLocation) -> String {
+    format!("the offending reference is {location} of this string literal")
+}
+
 // The public docs for this macro live in the sql-conditional-queries crate.
-#[proc_macro_error2::proc_macro_error]
+//
+// `proc_macro_hack` makes `proc_macro_error` wrap the emitted errors in a block, which is required
+// for emitting more than one of them in expression position.
+#[proc_macro_error2::proc_macro_error(proc_macro_hack)]
 #[proc_macro]
 pub fn conditional_query_as(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     conditional_query_as_inner(input, true)
 }
 
 // The public docs for this macro live in the sql-conditional-queries crate.
-#[proc_macro_error2::proc_macro_error]
+#[proc_macro_error2::proc_macro_error(proc_macro_hack)]
 #[proc_macro]
 pub fn conditional_query_as_unchecked(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     conditional_query_as_inner(input, false)
@@ -34,60 +158,172 @@ fn conditional_query_as_inner(
     let input: proc_macro2::TokenStream = input.into();
 
     let ts = match sqlx_conditional_queries_core::conditional_query_as(
-        DATABASE_TYPE,
+        database_type,
+        ANY_DATABASE_TYPES,
         input,
         checked,
+        sql_comment().as_ref(),
+        cfg!(feature = "capture-arguments"),
     ) {
         Ok(ts) => ts,
         Err(Error::SynError(err)) => {
             return err.to_compile_error().into();
         }
-        Err(Error::AnalyzeError(err)) => match err {
-            AnalyzeError::ExpectedStringLiteral(span) => abort!(
-                span,
-                "expected string literal";
-                help = "only string literals or tuples of string literals are supported in compile-time bindings";
-            ),
-            AnalyzeError::BindingNameValueLengthMismatch {
-                names,
-                names_span,
-                values,
-                values_span,
-            } => abort!(
-                names_span,
-                "mismatch between number of names and values";
-                names = names_span => "number of names: {}", names;
-                values = values_span => "number of values: {}", values;
-            ),
-            AnalyzeError::DuplicatedCompileTimeBindingsFound { first: _, second } => {
-                abort!(second.span(), "found duplicate compile-time binding")
-            }
-            AnalyzeError::CompileTimeBindingCycleDetected { root_ident, path } => abort!(
-                root_ident.span(),
-                "detected compile-time binding cycle: {}",
-                path
-            ),
-        },
-        Err(Error::ExpandError(err)) => match err {
-            // TODO: Make this span point at the binding reference.  Requires https://github.com/rust-lang/rust/issues/54725
-            ExpandError::MissingCompileTimeBinding(binding, span) => abort!(
-                span,
-                "missing compile-time binding";
-                help = "found no compile-time binding with the specified name: {}", binding;
-            ),
-            // TODO: Make this span point at the opening brace.  Requires https://github.com/rust-lang/rust/issues/54725
-            ExpandError::MissingBindingClosingBrace(span) => abort!(
-                span,
-                "missing closing brace for compile-time binding reference"
-            ),
-            ExpandError::BindingReferenceTypeOverrideParseError(err, span) => abort!(
-                span,
-                "failed to parse type override in binding reference: {}",
-                err
-            ),
-        },
+        Err(Error::AnalyzeErrors(errors)) => {
+            errors.into_iter().for_each(emit_analyze_error);
+            // The emitted errors replace the output of the macro.
+            return proc_macro::TokenStream::new();
+        }
+        Err(Error::ExpandErrors(errors)) => {
+            errors.into_iter().for_each(emit_expand_error);
+            return proc_macro::TokenStream::new();
+        }
+        Err(Error::Lints(lints)) => {
+            lints.into_iter().for_each(emit_lint);
+            return proc_macro::TokenStream::new();
+        }
     };
 
     let output: proc_macro::TokenStream = ts.into();
     output
 }
+
+/// Emits a diagnostic for an error found while analyzing the compile-time bindings.
+fn emit_analyze_error(err: AnalyzeError) {
+    match err {
+        AnalyzeError::ExpectedStringLiteral(span) => emit_error!(
+            span,
+            "expected string literal";
+            help = "only string literals or tuples of string literals are supported in compile-time bindings";
+        ),
+        AnalyzeError::BindingNameValueLengthMismatch {
+            names,
+            names_span,
+            values,
+            values_span,
+        } => emit_error!(
+            names_span,
+            "mismatch between number of names and values";
+            names = names_span => "number of names: {}", names;
+            values = values_span => "number of values: {}", values;
+        ),
+        AnalyzeError::DuplicatedCompileTimeBindingsFound { first: _, second } => {
+            emit_error!(second.span(), "found duplicate compile-time binding")
+        }
+        AnalyzeError::CompileTimeBindingCycleDetected { root_ident, path } => emit_error!(
+            root_ident.span(),
+            "detected compile-time binding cycle: {}",
+            path
+        ),
+        AnalyzeError::InvalidArmLabel(span) => emit_error!(
+            span,
+            "invalid match arm label";
+            help = "match arms are labelled using `#[label = \"...\"]`";
+        ),
+        AnalyzeError::UnknownOmittedBinding { name, suggestion } => emit_error!(
+            name.span(),
+            "unknown compile-time binding in `count(omit = [...])`";
+            help =? suggestion.map(|suggestion| format!("did you mean `#{suggestion}`?"));
+            help = "only compile-time bindings declared in this macro invocation can be omitted";
+        ),
+        AnalyzeError::InvalidDialect(span) => emit_error!(
+            span,
+            "unknown dialect";
+            help = "dialect arms are selected by `postgres`, `mysql`, `sqlite` or `_`";
+        ),
+    }
+}
+
+/// Emits a diagnostic for an error found while expanding the query string.
+fn emit_expand_error(err: ExpandError) {
+    match err {
+        ExpandError::MissingCompileTimeBinding {
+            name,
+            suggestion,
+            location,
+        } => emit_error!(
+            location.span,
+            "missing compile-time binding";
+            help =? suggestion.map(|suggestion| format!("did you mean `{{#{suggestion}}}`?"));
+            help = "found no compile-time binding with the specified name: {}", name;
+            note = "{}", location_note(&location);
+        ),
+        ExpandError::MissingBindingClosingBrace(location) => emit_error!(
+            location.span,
+            "missing closing brace for compile-time binding reference";
+            note = "{}", location_note(&location);
+        ),
+        ExpandError::BindingReferenceTypeOverrideParseError(err, location) => emit_error!(
+            location.span,
+            "failed to parse type override in binding reference: {}", err;
+            note = "{}", location_note(&location);
+        ),
+        ExpandError::InvalidRunTimeBindingName(binding, location) => emit_error!(
+            location.span,
+            "invalid run-time binding name: `{}`", binding;
+            help = "run-time bindings reference a variable in scope by its name, e.g. `{id}`";
+            note = "{}", location_note(&location);
+        ),
+        ExpandError::ConflictingTypeOverrides {
+            name,
+            first,
+            second,
+            location,
+        } => {
+            let describe = |type_override: Option<String>| match type_override {
+                Some(type_override) => format!("`{{{name}:{type_override}}}`"),
+                None => format!("`{{{name}}}`"),
+            };
+            emit_error!(
+                location.span,
+                "conflicting type overrides for run-time binding `{}`", name;
+                note = "it's referenced both as {} and as {}", describe(first), describe(second);
+                note = "{}", location_note(&location);
+                help = "use the same type override for every reference";
+            )
+        }
+        ExpandError::MissingSqlCastType(binding, location) => emit_error!(
+            location.span,
+            "missing type in SQL cast of run-time binding `{}`", binding;
+            help = "name the SQL type after the double colon, e.g. `{{{}::int8}}`", binding;
+            note = "{}", location_note(&location);
+        ),
+        ExpandError::InvalidSqlCommentFormat(format) => emit_error!(
+            proc_macro2::Span::call_site(),
+            "invalid SQL comment format: {}", format;
+            help = "the format set in {} must not contain `*/`", SQL_COMMENT_FORMAT_VAR;
+        ),
+        ExpandError::MissingDialectArm {
+            names,
+            database_type,
+            span,
+        } => emit_error!(
+            span,
+            "dialect binding `{}` has no arm for {:?}", names, database_type;
+            help = "add an arm for the database, or a `_` arm as fallback";
+        ),
+    }
+}
+
+/// Emits a diagnostic for a lint denied using `unused_bindings = deny`.
+fn emit_lint(lint: Lint) {
+    match lint {
+        Lint::UnusedBinding(name) => emit_error!(
+            name.span(),
+            "unused compile-time binding `#{}`", name;
+            note = "every compile-time binding multiplies the number of variants";
+            help = "reference it as `{{#{}}}` in the query, or remove it", name;
+        ),
+        Lint::UnreachableBinding {
+            name,
+            referenced_from,
+        } => emit_error!(
+            name.span(),
+            "compile-time binding `#{}` is never used", name;
+            note = "it's only referenced from {}, which {} never used itself",
+                referenced_from.iter().map(|name| format!("`#{name}`")).collect::<Vec<_>>().join(", "),
+                if referenced_from.len() == 1 { "is" } else { "are" };
+            help = "reference it from the query, or remove it";
+        ),
+    }
+}
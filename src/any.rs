@@ -0,0 +1,592 @@
+//! Run-time dispatch for queries passing `database = any`, which run against `sqlx::Any`.
+//!
+//! Such an invocation evaluates to an [`AnyConditionalMap`], which holds the selected variant for
+//! every enabled database.  Its fetch methods ask the executor which backend it's connected to,
+//! and only then build and run the variant written for that backend.
+//!
+//! ```rust,ignore
+//! sqlx::any::install_default_drivers();
+//! let pool = AnyPool::connect(&database_url).await?;
+//!
+//! let users = conditional_query_as!(
+//!     User,
+//!     "SELECT id, name FROM users WHERE name = {name}",
+//!     database = any,
+//! )
+//! .fetch_all(&pool)
+//! .await?;
+//! ```
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_core::stream::{BoxStream, Stream};
+use sqlx_conditional_queries_core::DatabaseType;
+use sqlx_core::{
+    acquire::Acquire,
+    any::{Any, AnyConnection, AnyQueryResult},
+    executor::Executor,
+    pool::Pool,
+    Either, Error,
+};
+
+use crate::{
+    map::{
+        Branch, BuildQuery, CallSite, Companions, ConditionalMap, ConditionalQuery, PrepareError,
+        Variants,
+    },
+    observer::QueryObserver,
+};
+
+/// A database backend `sqlx::Any` can be connected to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    PostgreSql,
+    MySql,
+    Sqlite,
+}
+
+impl Backend {
+    /// Returns the backend with the given name, as returned by `AnyConnection::backend_name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "PostgreSQL" => Some(Self::PostgreSql),
+            "MySQL" => Some(Self::MySql),
+            "SQLite" => Some(Self::Sqlite),
+            _ => None,
+        }
+    }
+
+    /// Returns the backend named by the scheme of a database URL, accepting the same schemes as
+    /// SQLx.
+    pub fn from_url(url: &str) -> Option<Self> {
+        DatabaseType::from_url(url).map(Self::from)
+    }
+}
+
+impl From<DatabaseType> for Backend {
+    fn from(database_type: DatabaseType) -> Self {
+        match database_type {
+            DatabaseType::PostgreSql => Self::PostgreSql,
+            DatabaseType::MySql => Self::MySql,
+            DatabaseType::Sqlite => Self::Sqlite,
+        }
+    }
+}
+
+/// An executor for `sqlx::Any` which knows the backend it's connected to.
+pub trait AnyExecutor<'c>: Executor<'c, Database = Any> {
+    /// Returns the backend this executor is connected to, or `None` if it isn't one of the
+    /// supported backends.
+    fn backend(&self) -> Option<Backend>;
+}
+
+impl<'c> AnyExecutor<'c> for &'c mut AnyConnection {
+    fn backend(&self) -> Option<Backend> {
+        Backend::from_name(self.backend_name())
+    }
+}
+
+impl<'p> AnyExecutor<'p> for &'_ Pool<Any> {
+    fn backend(&self) -> Option<Backend> {
+        Backend::from_url(self.connect_options().database_url.as_str())
+    }
+}
+
+/// The type generated by [`conditional_query_as!`] for invocations passing `database = any`.
+///
+/// It exposes the methods of [`ConditionalMap`], and builds the variant written for the backend of
+/// the executor once it's known, binding only the arguments of that variant.
+///
+/// [`conditional_query_as!`]: crate::conditional_query_as
+pub struct AnyConditionalMap<S, M = Unmapped> {
+    select: S,
+    map: M,
+    observer: Option<Arc<dyn QueryObserver>>,
+}
+
+impl<S> AnyConditionalMap<S> {
+    #[doc(hidden)]
+    pub fn new(select: S) -> Self {
+        Self {
+            select,
+            map: Unmapped,
+            observer: None,
+        }
+    }
+}
+
+impl<S, M> AnyConditionalMap<S, M> {
+    /// Notifies `observer` about the execution of this query, see [`ConditionalMap::observe`].
+    pub fn observe(mut self, observer: Arc<dyn QueryObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+}
+
+impl<S, M, Q, V> AnyConditionalMap<S, M>
+where
+    S: SelectBackend<Query = ConditionalMap<Q, V>>,
+    V: Variants,
+{
+    /// Builds the query written for `backend`.
+    ///
+    /// Fails with `Error::Configuration` if the macro wasn't expanded for `backend` because its
+    /// feature is disabled.
+    fn query(&self, backend: Backend) -> Result<ConditionalMap<Q, V>, Error> {
+        let query = self.select.select(backend).ok_or_else(|| {
+            Error::Configuration(
+                format!("query wasn't generated for the {backend:?} backend").into(),
+            )
+        })?;
+        Ok(match &self.observer {
+            Some(observer) => query.observe(observer.clone()),
+            None => query,
+        })
+    }
+
+    /// Builds the query written for the backend of `executor`.
+    fn select<'c, E: AnyExecutor<'c>>(&self, executor: &E) -> Result<ConditionalMap<Q, V>, Error> {
+        let backend = executor.backend().ok_or_else(|| {
+            Error::Configuration("the executor is connected to an unsupported backend".into())
+        })?;
+        self.query(backend)
+    }
+
+    /// Returns the call site of the macro invocation this query was generated by, see
+    /// [`ConditionalMap::call_site`].
+    pub fn call_site(&self) -> &'static CallSite {
+        const { &CallSite::of::<V>() }
+    }
+
+    /// Prepares the SQL of every variant of this query written for the backend the connection
+    /// acquired from `executor` is connected to.
+    ///
+    /// See [`CallSite::prepare_all_any`].
+    pub fn prepare_all<'c, A>(
+        &self,
+        executor: A,
+    ) -> impl Future<Output = Result<(), PrepareError>> + use<'c, A, S, M, Q, V>
+    where
+        A: Acquire<'c, Database = Any>,
+    {
+        self.call_site().prepare_all_any(executor)
+    }
+
+    /// Returns the fully expanded SQL of the selected variant written for `backend`, or `None` if
+    /// the macro wasn't expanded for `backend`.
+    pub fn sql(&self, backend: Backend) -> Option<&'static str> {
+        self.select.select(backend).map(|query| query.sql())
+    }
+
+    /// Map each row in the result to another type.
+    ///
+    /// See [`ConditionalMap::map`]
+    #[allow(clippy::type_complexity)]
+    pub fn map<'q, O, G, P>(
+        self,
+        mut g: G,
+    ) -> AnyConditionalMap<S, TryMapped<M, impl FnMut(M::Output) -> Result<P, Error> + Send>>
+    where
+        Q: ConditionalQuery<'q, Any, Output = O>,
+        M: MapRows<O>,
+        G: FnMut(M::Output) -> P + Send,
+        P: Send + Unpin,
+    {
+        self.try_map(move |row| Ok(g(row)))
+    }
+
+    /// Map each row in the result to another type.
+    ///
+    /// See [`ConditionalMap::try_map`]
+    pub fn try_map<'q, O, G, P>(self, g: G) -> AnyConditionalMap<S, TryMapped<M, G>>
+    where
+        Q: ConditionalQuery<'q, Any, Output = O>,
+        M: MapRows<O>,
+        G: FnMut(M::Output) -> Result<P, Error> + Send,
+        P: Send + Unpin,
+    {
+        AnyConditionalMap {
+            select: self.select,
+            map: TryMapped(self.map, g),
+            observer: self.observer,
+        }
+    }
+
+    /// See [`ConditionalMap::fetch`]
+    pub fn fetch<'q, 'e, 'c: 'e, O, E>(self, executor: E) -> BoxStream<'e, Result<M::Output, Error>>
+    where
+        'q: 'e,
+        E: 'e + AnyExecutor<'c>,
+        O: 'e,
+        Q: ConditionalQuery<'q, Any, Output = O> + 'e,
+        M: MapRows<O> + 'e,
+        V: 'e,
+    {
+        match self.select(&executor) {
+            Ok(query) => {
+                let mut map = self.map;
+                query.try_map(move |row| map.map_row(row)).fetch(executor)
+            }
+            Err(error) => Box::pin(ErrorStream(Some(Err(error)))),
+        }
+    }
+
+    /// See [`ConditionalMap::fetch_unboxed`]
+    pub fn fetch_unboxed<'q, 'e, 'c: 'e, O, E>(
+        self,
+        executor: E,
+    ) -> impl Stream<Item = Result<M::Output, Error>> + Send + Unpin + use<'q, 'e, 'c, O, E, S, M, Q, V>
+    where
+        'q: 'e,
+        E: 'e + AnyExecutor<'c>,
+        O: 'e,
+        Q: ConditionalQuery<'q, Any, Output = O> + 'e,
+        M: MapRows<O> + 'e,
+        V: 'e,
+    {
+        match self.select(&executor) {
+            Ok(query) => {
+                let mut map = self.map;
+                Branch::Right(
+                    query
+                        .try_map(move |row| map.map_row(row))
+                        .fetch_unboxed(executor),
+                )
+            }
+            Err(error) => Branch::Left(ErrorStream(Some(Err(error)))),
+        }
+    }
+
+    /// See [`ConditionalMap::fetch_many`]
+    #[deprecated = "Only the SQLite driver supports multiple statements in one prepared statement and that behavior is deprecated. Use `sqlx::raw_sql()` instead. See https://github.com/launchbadge/sqlx/issues/3108 for discussion."]
+    #[allow(clippy::type_complexity)]
+    pub fn fetch_many<'q, 'e, 'c: 'e, O, E>(
+        self,
+        executor: E,
+    ) -> BoxStream<'e, Result<Either<AnyQueryResult, M::Output>, Error>>
+    where
+        'q: 'e,
+        E: 'e + AnyExecutor<'c>,
+        O: 'e,
+        Q: ConditionalQuery<'q, Any, Output = O> + 'e,
+        M: MapRows<O> + 'e,
+        V: 'e,
+    {
+        match self.select(&executor) {
+            Ok(query) => {
+                let mut map = self.map;
+                #[allow(deprecated)]
+                query
+                    .try_map(move |row| map.map_row(row))
+                    .fetch_many(executor)
+            }
+            Err(error) => Box::pin(ErrorStream(Some(Err(error)))),
+        }
+    }
+
+    /// See [`ConditionalMap::fetch_all`]
+    pub fn fetch_all<'q, 'e, 'c: 'e, O, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Vec<M::Output>, Error>> + Send + use<'q, 'e, 'c, O, E, S, M, Q, V>
+    where
+        'q: 'e,
+        E: 'e + AnyExecutor<'c>,
+        O: 'e,
+        Q: ConditionalQuery<'q, Any, Output = O> + 'e,
+        M: MapRows<O> + 'e,
+        V: 'e,
+    {
+        let query = self.select(&executor);
+        let mut map = self.map;
+        let rows = query.map(|query| {
+            query
+                .try_map(move |row| map.map_row(row))
+                .fetch_all(executor)
+        });
+        async move { rows?.await }
+    }
+
+    /// See [`ConditionalMap::fetch_one`]
+    pub fn fetch_one<'q, 'e, 'c: 'e, O, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<M::Output, Error>> + Send + use<'q, 'e, 'c, O, E, S, M, Q, V>
+    where
+        'q: 'e,
+        E: 'e + AnyExecutor<'c>,
+        O: 'e,
+        Q: ConditionalQuery<'q, Any, Output = O> + 'e,
+        M: MapRows<O> + 'e,
+        V: 'e,
+    {
+        let query = self.select(&executor);
+        let mut map = self.map;
+        let row = query.map(|query| {
+            query
+                .try_map(move |row| map.map_row(row))
+                .fetch_one(executor)
+        });
+        async move { row?.await }
+    }
+
+    /// See [`ConditionalMap::fetch_optional`]
+    pub fn fetch_optional<'q, 'e, 'c: 'e, O, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Option<M::Output>, Error>> + Send + use<'q, 'e, 'c, O, E, S, M, Q, V>
+    where
+        'q: 'e,
+        E: 'e + AnyExecutor<'c>,
+        O: 'e,
+        Q: ConditionalQuery<'q, Any, Output = O> + 'e,
+        M: MapRows<O> + 'e,
+        V: 'e,
+    {
+        let query = self.select(&executor);
+        let mut map = self.map;
+        let row = query.map(|query| {
+            query
+                .try_map(move |row| map.map_row(row))
+                .fetch_optional(executor)
+        });
+        async move { row?.await }
+    }
+}
+
+/// The companion queries generated by the macro's `count` option.
+impl<S, M, Q, C, X, V> AnyConditionalMap<S, M>
+where
+    S: SelectBackend<Query = ConditionalMap<Companions<Q, C, X>, V>>,
+    V: Variants,
+{
+    /// See [`ConditionalMap::count`]
+    pub fn count<'q, 'e, 'c: 'e, E>(
+        &self,
+        executor: E,
+    ) -> impl Future<Output = Result<i64, Error>> + Send + use<'q, 'e, 'c, E, S, M, Q, C, X, V>
+    where
+        'q: 'e,
+        E: 'e + AnyExecutor<'c>,
+        C: BuildQuery<Query: ConditionalQuery<'q, Any, Output = i64> + 'e>,
+    {
+        let count = self.select(&executor).map(|query| query.count(executor));
+        async move { count?.await }
+    }
+
+    /// See [`ConditionalMap::exists`]
+    pub fn exists<'q, 'e, 'c: 'e, E>(
+        &self,
+        executor: E,
+    ) -> impl Future<Output = Result<bool, Error>> + Send + use<'q, 'e, 'c, E, S, M, Q, C, X, V>
+    where
+        'q: 'e,
+        E: 'e + AnyExecutor<'c>,
+        X: BuildQuery<Query: ConditionalQuery<'q, Any, Output = ()> + 'e>,
+    {
+        let exists = self.select(&executor).map(|query| query.exists(executor));
+        async move { exists?.await }
+    }
+}
+
+/// Builds the query of the selected arm written for a backend.
+///
+/// This is implemented for the closures generated for every arm of an invocation passing
+/// `database = any`, and for [`Branch`], which selects between two of them.
+#[doc(hidden)]
+pub trait SelectBackend {
+    type Query;
+
+    fn select(&self, backend: Backend) -> Option<Self::Query>;
+}
+
+impl<F, Q> SelectBackend for F
+where
+    F: Fn(Backend) -> Option<Q>,
+{
+    type Query = Q;
+
+    fn select(&self, backend: Backend) -> Option<Q> {
+        self(backend)
+    }
+}
+
+impl<L, R> SelectBackend for Branch<L, R>
+where
+    L: SelectBackend,
+    R: SelectBackend<Query = L::Query>,
+{
+    type Query = L::Query;
+
+    fn select(&self, backend: Backend) -> Option<Self::Query> {
+        match self {
+            Branch::Left(left) => left.select(backend),
+            Branch::Right(right) => right.select(backend),
+        }
+    }
+}
+
+/// Maps the rows of an [`AnyConditionalMap`], which is applied once the query is built.
+#[doc(hidden)]
+pub trait MapRows<O>: Send {
+    type Output: Send + Unpin;
+
+    fn map_row(&mut self, row: O) -> Result<Self::Output, Error>;
+}
+
+/// Leaves the rows as they are decoded by the macro.
+#[doc(hidden)]
+pub struct Unmapped;
+
+impl<O: Send + Unpin> MapRows<O> for Unmapped {
+    type Output = O;
+
+    fn map_row(&mut self, row: O) -> Result<O, Error> {
+        Ok(row)
+    }
+}
+
+/// Applies `G` on top of the mapping `M`, see [`AnyConditionalMap::try_map`].
+#[doc(hidden)]
+pub struct TryMapped<M, G>(M, G);
+
+impl<O, M, G, P> MapRows<O> for TryMapped<M, G>
+where
+    M: MapRows<O>,
+    G: FnMut(M::Output) -> Result<P, Error> + Send,
+    P: Send + Unpin,
+{
+    type Output = P;
+
+    fn map_row(&mut self, row: O) -> Result<P, Error> {
+        (self.1)(self.0.map_row(row)?)
+    }
+}
+
+impl CallSite {
+    /// Like [`prepare_all`](Self::prepare_all), but prepares the variants written for the backend
+    /// the connection acquired from `executor` is connected to.
+    pub async fn prepare_all_any<'c, A>(&self, executor: A) -> Result<(), PrepareError>
+    where
+        A: Acquire<'c, Database = Any>,
+    {
+        let mut connection = executor.acquire().await.map_err(PrepareError::Acquire)?;
+        let database = connection.backend_name().to_owned();
+        self.prepare_variants::<Any>(&mut connection, &database)
+            .await
+    }
+}
+
+/// A stream yielding a single error, returned by the streaming methods of [`AnyConditionalMap`] if
+/// no query was generated for the backend of the executor.
+struct ErrorStream<T>(Option<Result<T, Error>>);
+
+impl<T: Unpin> Stream for ErrorStream<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.0.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{
+        any::{AnyArguments, AnyPoolOptions, AnyRow},
+        query::Map,
+        AnyPool, Row,
+    };
+
+    use super::*;
+    use crate::observer::{RecordingObserver, SourceLocation};
+
+    type RowMapper = fn(AnyRow) -> Result<String, Error>;
+
+    struct TestVariants;
+
+    impl Variants for TestVariants {
+        const VARIANTS: &'static [&'static str] = &["SELECT 'postgres'", "SELECT 'sqlite'"];
+        const IDS: &'static [&'static str] = &["a#00000001", "a#00000002"];
+        const LABELS: &'static [&'static str] = &["a", "a"];
+        const ARGUMENTS: &'static [usize] = &[0, 0];
+        const DATABASES: &'static [&'static str] = &["PostgreSQL", "SQLite"];
+        const LOCATION: SourceLocation = SourceLocation {
+            file: "src/any.rs",
+            line: 1,
+            column: 1,
+        };
+    }
+
+    type TestQuery =
+        ConditionalMap<Map<'static, Any, RowMapper, AnyArguments<'static>>, TestVariants>;
+
+    /// Returns the query running the given variant of `TestVariants`.
+    fn query(variant: usize) -> TestQuery {
+        ConditionalMap::new(
+            sqlx::query::<Any>(TestVariants::VARIANTS[variant])
+                .try_map((|row: AnyRow| row.try_get(0)) as RowMapper),
+            variant,
+        )
+    }
+
+    async fn pool() -> AnyPool {
+        sqlx::any::install_default_drivers();
+        AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn runs_the_variant_of_the_executors_backend() {
+        let pool = pool().await;
+        let observer = Arc::new(RecordingObserver::default());
+
+        let built = std::cell::RefCell::new(Vec::new());
+        let select = |backend| {
+            built.borrow_mut().push(backend);
+            match backend {
+                Backend::PostgreSql => Some(query(0)),
+                Backend::Sqlite => Some(query(1)),
+                Backend::MySql => None,
+            }
+        };
+        let query = AnyConditionalMap::new(select).observe(observer.clone());
+        assert_eq!(query.sql(Backend::PostgreSql), Some("SELECT 'postgres'"));
+        assert_eq!(query.sql(Backend::MySql), None);
+        built.borrow_mut().clear();
+
+        let rows = query.map(|row| row.len()).fetch_all(&pool).await.unwrap();
+        assert_eq!(rows, [6]);
+        // Only the variant of the executor's backend is built.
+        assert_eq!(*built.borrow(), [Backend::Sqlite]);
+
+        let queries = observer.queries();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].info.variant_id, "a#00000002");
+    }
+
+    #[tokio::test]
+    async fn fails_without_a_variant_for_the_executors_backend() {
+        let pool = pool().await;
+
+        let select = |backend| match backend {
+            Backend::PostgreSql => Some(query(0)),
+            Backend::MySql | Backend::Sqlite => None,
+        };
+        let error = AnyConditionalMap::new(select)
+            .fetch_one(&pool)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, Error::Configuration(message) if message.to_string().contains("Sqlite")),
+            "{error:?}"
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! Tracking which variants of conditional queries were executed.
+//!
+//! With the `coverage` feature enabled, every call to one of the fetch methods of a conditional
+//! query counts as an execution of the selected variant in a process-wide registry.  Calling
+//! [`report`] at the end of a test run then lists the variants which were never executed,
+//! including those of call sites which were never reached at all, since all [call sites] are
+//! registered when the program starts.
+//!
+//! Queries passing `database = any` have variants for every enabled database, but only run the
+//! ones written for the database they're executed against.  Their variants are therefore only
+//! reported for databases which at least one of them was executed against.
+//!
+//! ```rust,ignore
+//! let report = sqlx_conditional_queries::coverage::report();
+//! assert!(report.is_complete(), "{report}");
+//! ```
+//!
+//! [call sites]: crate::map::call_sites
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Mutex,
+};
+
+use crate::{
+    map::{self, CallSite, Variants},
+    observer::SourceLocation,
+};
+
+type Key = (SourceLocation, &'static [&'static str]);
+
+/// The execution counts of the variants of every call site.
+static REGISTRY: Mutex<BTreeMap<Key, (CallSite, Vec<u64>)>> = Mutex::new(BTreeMap::new());
+
+/// The databases queries passing `database = any` were executed against.
+static DATABASES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+fn executions<'a>(
+    registry: &'a mut BTreeMap<Key, (CallSite, Vec<u64>)>,
+    site: &CallSite,
+) -> &'a mut Vec<u64> {
+    &mut registry
+        .entry((site.location, site.ids))
+        .or_insert_with(|| (*site, vec![0; site.ids.len()]))
+        .1
+}
+
+/// Counts an execution of the given variant of `V`.
+pub(crate) fn record<V: Variants>(variant: usize) {
+    if let Some(&database) = V::DATABASES.get(variant) {
+        let mut databases = DATABASES.lock().unwrap_or_else(|err| err.into_inner());
+        databases.insert(database);
+    }
+
+    let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    executions(&mut registry, &CallSite::of::<V>())[variant] += 1;
+}
+
+/// Resets the execution counts of all registered variants to zero.
+pub fn reset() {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    for (_, executions) in registry.values_mut() {
+        executions.fill(0);
+    }
+    DATABASES
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clear();
+}
+
+/// Returns the execution counts of all variants of all call sites.
+pub fn report() -> CoverageReport {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    for site in map::call_sites() {
+        executions(&mut registry, site);
+    }
+
+    let databases = DATABASES.lock().unwrap_or_else(|err| err.into_inner());
+    let sites = registry
+        .values()
+        .map(|(site, executions)| SiteCoverage {
+            location: site.location,
+            variants: site
+                .ids
+                .iter()
+                .zip(executions)
+                .enumerate()
+                .filter(|&(variant, _)| {
+                    site.databases
+                        .get(variant)
+                        .is_none_or(|database| databases.contains(database))
+                })
+                .map(|(_, (&id, &executions))| VariantCoverage { id, executions })
+                .collect(),
+        })
+        .filter(|site: &SiteCoverage| !site.variants.is_empty())
+        .collect();
+
+    CoverageReport { sites }
+}
+
+/// The variant coverage of all registered call sites, ordered by their location.
+///
+/// Its `Display` implementation lists the variants which were never executed.
+#[derive(Clone, Debug)]
+pub struct CoverageReport {
+    pub sites: Vec<SiteCoverage>,
+}
+
+/// The variant coverage of a single call site.
+#[derive(Clone, Debug)]
+pub struct SiteCoverage {
+    pub location: SourceLocation,
+    pub variants: Vec<VariantCoverage>,
+}
+
+/// How often a single variant was executed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VariantCoverage {
+    /// The identifier of the variant, see [`ConditionalMap::variant_id`].
+    ///
+    /// [`ConditionalMap::variant_id`]: crate::ConditionalMap::variant_id
+    pub id: &'static str,
+    pub executions: u64,
+}
+
+impl CoverageReport {
+    /// Returns the call sites and variants which were never executed.
+    pub fn uncovered(&self) -> impl Iterator<Item = (SourceLocation, &VariantCoverage)> {
+        self.sites.iter().flat_map(|site| {
+            site.variants
+                .iter()
+                .filter(|variant| variant.executions == 0)
+                .map(|variant| (site.location, variant))
+        })
+    }
+
+    /// Returns whether every variant of every registered call site was executed.
+    pub fn is_complete(&self) -> bool {
+        self.uncovered().next().is_none()
+    }
+
+    /// Returns the coverage of the call sites in the given file.
+    ///
+    /// `file` is matched against the path returned by [`file!`].
+    pub fn file(&self, file: &str) -> Self {
+        Self {
+            sites: self
+                .sites
+                .iter()
+                .filter(|site| site.location.file == file)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total: usize = self.sites.iter().map(|site| site.variants.len()).sum();
+        let uncovered: Vec<_> = self.uncovered().collect();
+        writeln!(
+            f,
+            "{} of {} variants in {} call sites were never executed",
+            uncovered.len(),
+            total,
+            self.sites.len(),
+        )?;
+        for (location, variant) in uncovered {
+            writeln!(f, "  {location}: {}", variant.id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestVariants;
+
+    impl Variants for TestVariants {
+        const VARIANTS: &'static [&'static str] = &["SELECT 1", "SELECT 2"];
+        const IDS: &'static [&'static str] = &["true#00000001", "false#00000002"];
+        const LABELS: &'static [&'static str] = &["true", "false"];
+        const ARGUMENTS: &'static [usize] = &[0, 0];
+        const LOCATION: SourceLocation = SourceLocation {
+            file: "src/coverage.rs",
+            line: 1,
+            column: 1,
+        };
+    }
+
+    /// The variants of a call site which is never executed.
+    struct UnreachedVariants;
+
+    impl Variants for UnreachedVariants {
+        const VARIANTS: &'static [&'static str] = &["SELECT 3"];
+        const IDS: &'static [&'static str] = &["unreached#00000003"];
+        const LABELS: &'static [&'static str] = &["unreached"];
+        const ARGUMENTS: &'static [usize] = &[0];
+        const LOCATION: SourceLocation = SourceLocation {
+            file: "src/coverage.rs",
+            line: 2,
+            column: 1,
+        };
+    }
+
+    inventory::submit! { CallSite::of::<UnreachedVariants>() }
+
+    #[test]
+    fn reports_uncovered_variants() {
+        record::<TestVariants>(0);
+        record::<TestVariants>(0);
+
+        let report = report().file("src/coverage.rs");
+        assert_eq!(report.sites.len(), 2);
+        assert_eq!(
+            report.sites[0].variants,
+            [
+                VariantCoverage {
+                    id: "true#00000001",
+                    executions: 2,
+                },
+                VariantCoverage {
+                    id: "false#00000002",
+                    executions: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            report.sites[1].variants,
+            [VariantCoverage {
+                id: "unreached#00000003",
+                executions: 0,
+            }]
+        );
+        assert!(!report.is_complete());
+        assert_eq!(
+            report.to_string(),
+            "2 of 3 variants in 2 call sites were never executed\n  \
+             src/coverage.rs:1:1: false#00000002\n  \
+             src/coverage.rs:2:1: unreached#00000003\n",
+        );
+    }
+
+    /// The variants of a call site passing `database = any`.
+    struct AnyVariants;
+
+    impl Variants for AnyVariants {
+        const VARIANTS: &'static [&'static str] = &["SELECT $1", "SELECT ?1"];
+        const IDS: &'static [&'static str] = &["any#00000004", "any#00000005"];
+        const LABELS: &'static [&'static str] = &["any", "any"];
+        const ARGUMENTS: &'static [usize] = &[1, 1];
+        const DATABASES: &'static [&'static str] = &["PostgreSQL", "SQLite"];
+        const LOCATION: SourceLocation = SourceLocation {
+            file: "src/coverage/any.rs",
+            line: 1,
+            column: 1,
+        };
+    }
+
+    #[test]
+    fn reports_variants_of_executed_databases() {
+        record::<AnyVariants>(1);
+
+        let report = report().file("src/coverage/any.rs");
+        assert_eq!(
+            report.sites[0].variants,
+            [VariantCoverage {
+                id: "any#00000005",
+                executions: 1,
+            }]
+        );
+        assert!(report.is_complete());
+    }
+}
@@ -0,0 +1,101 @@
+//! `tracing` integration for the fetch methods of [`ConditionalMap`](crate::ConditionalMap).
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+use sqlx_core::Error;
+use tracing::{field, Instrument, Span};
+
+/// Creates the span a single fetch of the given variant runs in.
+pub(crate) fn span(method: &'static str, label: &str, sql: &str, arguments: usize) -> Span {
+    tracing::info_span!(
+        "conditional_query",
+        method,
+        variant = label,
+        sql,
+        arguments,
+        bindings = field::Empty,
+        rows = field::Empty,
+        error = field::Empty,
+    )
+}
+
+fn record_error(span: &Span, error: &Error) {
+    span.record("error", field::display(error));
+}
+
+/// Runs `future` inside `span`, recording the number of returned rows or the error.
+pub(crate) fn rows<T>(
+    span: Span,
+    future: impl Future<Output = Result<Vec<T>, Error>> + Send,
+) -> impl Future<Output = Result<Vec<T>, Error>> + Send {
+    let record_span = span.clone();
+    async move {
+        let result = future.await;
+        match &result {
+            Ok(rows) => {
+                record_span.record("rows", rows.len());
+            }
+            Err(error) => record_error(&record_span, error),
+        }
+        result
+    }
+    .instrument(span)
+}
+
+/// Runs `future` inside `span`, recording the error if it fails.
+pub(crate) fn result<T>(
+    span: Span,
+    future: impl Future<Output = Result<T, Error>> + Send,
+) -> impl Future<Output = Result<T, Error>> + Send {
+    let record_span = span.clone();
+    async move {
+        let result = future.await;
+        if let Err(error) = &result {
+            record_error(&record_span, error);
+        }
+        result
+    }
+    .instrument(span)
+}
+
+pin_project! {
+    /// A stream which is polled inside a span, recording any error it yields.
+    pub(crate) struct InstrumentedStream<S> {
+        #[pin]
+        inner: S,
+        span: Span,
+    }
+}
+
+impl<S> InstrumentedStream<S> {
+    pub(crate) fn new(inner: S, span: Span) -> Self {
+        Self { inner, span }
+    }
+}
+
+impl<S, T> Stream for InstrumentedStream<S>
+where
+    S: Stream<Item = Result<T, Error>>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let _entered = this.span.enter();
+        let item = this.inner.poll_next(cx);
+        if let Poll::Ready(Some(Err(error))) = &item {
+            record_error(this.span, error);
+        }
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
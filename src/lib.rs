@@ -1,9 +1,20 @@
 #![doc = include_str!("../README.md")]
 
+pub use map::ConditionalMap;
+
+#[cfg(feature = "any")]
+pub mod any;
+#[cfg(feature = "coverage")]
+pub mod coverage;
+#[cfg(feature = "tracing")]
+mod instrument;
+pub mod map;
+pub mod observer;
+
 /// # Emit conditional compile-time verified `query_as!` invocations
 ///
-/// The generated type exposes the same methods as `sqlx::query::Map`, with the exception of `map`
-/// and `try_map`.
+/// The macro evaluates to a [`ConditionalMap`], which exposes the same methods as
+/// `sqlx::query::Map`.
 ///
 ///
 /// ## Bound parameters
@@ -23,9 +34,17 @@
 /// type to pass to `query_as!`.
 ///
 /// You can pass type overrides to SQLx using a colon after the binding reference.  E.g.
-/// `{foo:_}`
+/// `{foo:_}`.  Since all references to a binding are bound from the same variable, they must all
+/// use the same type override.
+///
+/// A double colon instead casts the parameter in SQL, so the database sees the right type.  E.g.
+/// `{ids::int8[]}` is rendered as `$1::int8[]` on PostgreSQL and as `CAST(? AS int8[])` on MySQL
+/// and SQLite.  Both can be combined by putting the type override after the cast, as in
+/// `{ids::int8[]:Vec<i64>}`.
 ///
-/// Which kind of bound parameter references are generated depends on the activated features.
+/// Which kind of bound parameter references are generated depends on the selected database.
+/// PostgreSQL and SQLite use numbered references such as `$1` and `?1`, so a parameter referenced
+/// multiple times is only bound once, while MySQL uses `?` and binds it again for every reference.
 ///
 ///
 /// ### Compile-time bound parameters
@@ -47,6 +66,146 @@
 /// ```
 ///
 ///
+/// ### Dialect bindings
+///
+/// Fragments which differ between databases can be bound using `dialect` instead of `match`.  The
+/// arm for the database the query is compiled for is chosen at compile-time, so dialect bindings
+/// don't add any variants.  Arms name one or more of `postgres`, `mysql` and `sqlite`, and `_`
+/// matches any database:
+///
+/// ```rust,ignore
+/// #now = dialect {
+///     postgres | mysql => "NOW()",
+///     sqlite => "CURRENT_TIMESTAMP",
+/// },
+/// #like = dialect {
+///     postgres => "ILIKE",
+///     _ => "LIKE",
+/// },
+/// ```
+///
+///
+/// ### Variant labels
+///
+/// Every generated variant has a label, made up of the labels of the match arms it was generated
+/// from, and an identifier which additionally contains a hash of the variant's SQL.  Both are
+/// exposed through [`ConditionalMap::variant_label`] and [`ConditionalMap::variant_id`].
+///
+/// By default the label of a match arm is its pattern, but it can be given a more meaningful name
+/// using a `label` attribute:
+///
+/// ```rust,ignore
+/// #limit = match limit {
+///     #[label = "with_limit"]
+///     Some(_) => "LIMIT {limit}",
+///     #[label = "without_limit"]
+///     None => "",
+/// }
+/// ```
+///
+/// ### Selecting the database
+///
+/// The database the query is expanded for, which determines the syntax of run-time bound
+/// parameters, is named by the scheme of `DATABASE_URL` if it's set, and is otherwise selected by
+/// the enabled features.  Passing the `database` option overrides it for a single invocation:
+///
+/// ```rust,ignore
+/// conditional_query_as!(
+///     OutputType,
+///     "SELECT id, name FROM users WHERE id = {id}",
+///     database = sqlite,
+/// )
+/// ```
+///
+/// This only changes the syntax the query is generated in, as SQLx still checks every query
+/// against the database `DATABASE_URL` points to.  Checked queries for any other database need
+/// SQLx's offline data, as saved by `cargo sqlx prepare` and used with `SQLX_OFFLINE=true`, or can
+/// use [`conditional_query_as_unchecked!`] instead.
+///
+/// ### Using `sqlx::Any`
+///
+/// With the `any` feature enabled, passing `database = any` expands the query once for every
+/// enabled database feature, and the invocation evaluates to an `any::AnyConditionalMap` whose
+/// fetch methods build and run the variant written for the backend the `AnyPool` or
+/// `AnyConnection` is connected to.  Since SQLx can't check queries against `sqlx::Any`, the rows
+/// are decoded using the output type's `sqlx::FromRow` implementation, and type overrides of
+/// run-time bound parameters are ignored.
+///
+/// ```rust,ignore
+/// conditional_query_as!(
+///     OutputType,
+///     "SELECT id, name FROM users WHERE id = {id}",
+///     database = any,
+/// )
+/// .fetch_one(&any_pool)
+/// .await?;
+/// ```
+///
+/// ### Keyset pagination
+///
+/// A `keyset` binding generates the predicate and the `ORDER BY` and `LIMIT` clauses of keyset
+/// pagination as two compile-time bindings.  `keys` lists the sort columns in order of precedence,
+/// `direction` is either `asc`, `desc` or an expression evaluating to a [`SortDirection`], and
+/// `cursor` is a variable holding an `Option` of a struct with a field named after every key, which
+/// is borrowed by the query.  The optional `limit` names a run-time binding holding the page size.
+///
+/// ```rust,ignore
+/// conditional_query_as!(
+///     OutputType,
+///     "SELECT id, created_at FROM posts WHERE {#after} {#page}",
+///     #(after, page) = keyset {
+///         keys: [created_at, id],
+///         direction: direction,
+///         cursor: last_post,
+///         limit: page_size,
+///     },
+/// )
+/// ```
+///
+/// Without a cursor the predicate is `1 = 1`, so the first page is selected.  The row comparison
+/// is spelled out on MySQL, e.g. `(created_at > ? OR (created_at = ? AND id > ?))`.
+///
+/// ### Counting rows
+///
+/// Passing the `count` option generates companion queries for every variant, which wrap its SQL
+/// to count the rows it returns or to check whether it returns any.  They're exposed through
+/// [`ConditionalMap::count`] and [`ConditionalMap::exists`], and are compile-time checked just
+/// like the variants themselves.  Compile-time bindings listed in `count(omit = [...])` are
+/// replaced with empty strings in the companion queries, which is useful to drop pagination:
+///
+/// ```rust,ignore
+/// let query = conditional_query_as!(
+///     OutputType,
+///     "SELECT id, name FROM users {#filter} ORDER BY id {#page}",
+///     #filter = match name { /* ... */ },
+///     #page = match page {
+///         Some(_) => "LIMIT {page_size} OFFSET {offset}",
+///         None => "",
+///     },
+///     count(omit = [#page]),
+/// );
+/// let total = query.count(&pool).await?;
+/// let users = query.fetch_all(&pool).await?;
+/// ```
+///
+/// ### Unused bindings
+///
+/// Every compile-time binding multiplies the number of variants, even if its value never ends up
+/// in the query.  A warning is therefore emitted for every binding which isn't referenced from the
+/// query string, and for every binding which is only referenced from the values of such bindings.
+/// All arms of a `dialect` binding count as used.  Passing `unused_bindings = allow` silences the
+/// warnings, and `unused_bindings = deny` turns them into errors:
+///
+/// ```rust,ignore
+/// conditional_query_as!(
+///     OutputType,
+///     "SELECT id, name FROM users {#filter}",
+///     #filter = match name { /* ... */ },
+///     unused_bindings = deny,
+/// )
+/// ```
+///
+///
 /// ## Examples
 ///
 /// ```rust,ignore
@@ -83,9 +242,47 @@ pub use sqlx_conditional_queries_macros::conditional_query_as;
 /// # Emit conditional `query_as_unchecked!` invocations
 pub use sqlx_conditional_queries_macros::conditional_query_as_unchecked;
 
+/// The direction of a [keyset pagination](conditional_query_as#keyset-pagination).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+/// Registers the call site described by the given [`Variants`](map::Variants) type, see
+/// [`map::call_sites`].
+#[cfg(feature = "call-sites")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __register_call_site {
+    ($variants:ty) => {
+        $crate::exports::inventory::submit! {
+            $crate::exports::CallSite::of::<$variants>()
+        }
+    };
+}
+
+/// Registering call sites requires the `call-sites` feature.
+#[cfg(not(feature = "call-sites"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __register_call_site {
+    ($variants:ty) => {};
+}
+
 /// Do not use this module.  It is only meant to be used by the generated by
 /// [`conditional_query_as!`] macro.
 #[doc(hidden)]
 pub mod exports {
-    pub use futures_core::stream::BoxStream;
+    #[cfg(feature = "call-sites")]
+    pub use inventory;
+
+    pub use crate::{
+        __register_call_site as register_call_site,
+        map::{Branch, CallSite, Companions, ConditionalMap, Variants},
+        observer::SourceLocation,
+    };
+
+    #[cfg(feature = "any")]
+    pub use crate::any::{AnyConditionalMap, Backend};
 }
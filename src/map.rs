@@ -0,0 +1,1605 @@
+//! Run-time types backing the queries generated by [`conditional_query_as!`].
+//!
+//! Every macro invocation evaluates to a [`ConditionalMap`] wrapping a tree of [`Branch`]es whose
+//! leaves are the `sqlx::query::Map`s emitted for each variant.  The fetch methods are written
+//! once here, so the macro only has to emit the `match` that picks the active leaf.
+//!
+//! [`conditional_query_as!`]: crate::conditional_query_as
+
+use std::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_core::stream::{BoxStream, Stream};
+use pin_project_lite::pin_project;
+use sqlx_core::{
+    acquire::Acquire,
+    database::Database,
+    error::BoxDynError,
+    executor::{Execute, Executor},
+    query::Map,
+    Either, Error,
+};
+
+use crate::observer::{
+    self, ObservedStream, PendingObservation, QueryInfo, QueryObserver, SourceLocation,
+};
+
+/// A query that can be executed through one of several generated variants.
+///
+/// This is implemented for `sqlx::query::Map`, which forms the leaves of a conditional query,
+/// for [`Branch`], which selects between two sub-trees, and for [`Companions`].  You should rarely
+/// have to interact with this trait directly, as [`ConditionalMap`] exposes all of its methods.
+pub trait ConditionalQuery<'q, DB: Database>: Send + Sized {
+    /// The type each row is mapped to.
+    type Output: Send + Unpin;
+
+    /// The stream returned by [`fetch_unboxed`](Self::fetch_unboxed).
+    ///
+    /// It's required to be `Unpin`, so that mapping the rows of a query doesn't hide that its
+    /// stream is.
+    type Stream<'e>: Stream<Item = Result<Self::Output, Error>> + Send + Unpin + 'e
+    where
+        'q: 'e,
+        Self: 'e,
+        Self::Output: 'e;
+
+    /// See [`sqlx::query::Map::fetch`](Map::fetch)
+    fn fetch<'e, 'c: 'e, E>(self, executor: E) -> BoxStream<'e, Result<Self::Output, Error>>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e;
+
+    /// Like [`fetch`](Self::fetch), but returns a stream which dispatches to the selected
+    /// variant's stream instead of boxing it.
+    fn fetch_unboxed<'e, 'c: 'e, E>(self, executor: E) -> Self::Stream<'e>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e;
+
+    /// See [`sqlx::query::Map::fetch_many`](Map::fetch_many)
+    #[deprecated = "Only the SQLite driver supports multiple statements in one prepared statement and that behavior is deprecated. Use `sqlx::raw_sql()` instead. See https://github.com/launchbadge/sqlx/issues/3108 for discussion."]
+    #[allow(clippy::type_complexity)]
+    fn fetch_many<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> BoxStream<'e, Result<Either<DB::QueryResult, Self::Output>, Error>>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e;
+
+    /// See [`sqlx::query::Map::fetch_all`](Map::fetch_all)
+    fn fetch_all<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Vec<Self::Output>, Error>> + Send + 'e
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e;
+
+    /// See [`sqlx::query::Map::fetch_one`](Map::fetch_one)
+    fn fetch_one<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Self::Output, Error>> + Send + 'e
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e;
+
+    /// See [`sqlx::query::Map::fetch_optional`](Map::fetch_optional)
+    fn fetch_optional<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Option<Self::Output>, Error>> + Send + 'e
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e;
+
+    /// Composes `g` on top of the row mapper of every variant.
+    ///
+    /// See [`sqlx::query::Map::try_map`](Map::try_map)
+    fn try_map<G, P>(self, g: G) -> impl ConditionalQuery<'q, DB, Output = P>
+    where
+        G: FnMut(Self::Output) -> Result<P, Error> + Send,
+        P: Send + Unpin;
+}
+
+impl<'q, DB, F, O, A> ConditionalQuery<'q, DB> for Map<'q, DB, F, A>
+where
+    DB: Database,
+    F: FnMut(DB::Row) -> Result<O, Error> + Send,
+    O: Send + Unpin,
+    A: 'q + Send + sqlx_core::arguments::IntoArguments<'q, DB>,
+{
+    type Output = O;
+
+    type Stream<'e>
+        = BoxStream<'e, Result<O, Error>>
+    where
+        'q: 'e,
+        Self: 'e,
+        O: 'e;
+
+    fn fetch<'e, 'c: 'e, E>(self, executor: E) -> BoxStream<'e, Result<O, Error>>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        O: 'e,
+    {
+        Map::fetch(self, executor)
+    }
+
+    fn fetch_unboxed<'e, 'c: 'e, E>(self, executor: E) -> BoxStream<'e, Result<O, Error>>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        O: 'e,
+    {
+        // The stream returned by SQLx is already boxed, so there's nothing to gain by wrapping it.
+        Map::fetch(self, executor)
+    }
+
+    fn fetch_many<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> BoxStream<'e, Result<Either<DB::QueryResult, O>, Error>>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        O: 'e,
+    {
+        #[allow(deprecated)]
+        Map::fetch_many(self, executor)
+    }
+
+    fn fetch_all<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Vec<O>, Error>> + Send + 'e
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        O: 'e,
+    {
+        Map::fetch_all(self, executor)
+    }
+
+    fn fetch_one<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<O, Error>> + Send + 'e
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        O: 'e,
+    {
+        Map::fetch_one(self, executor)
+    }
+
+    fn fetch_optional<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Option<O>, Error>> + Send + 'e
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        O: 'e,
+    {
+        Map::fetch_optional(self, executor)
+    }
+
+    fn try_map<G, P>(self, g: G) -> impl ConditionalQuery<'q, DB, Output = P>
+    where
+        G: FnMut(O) -> Result<P, Error> + Send,
+        P: Send + Unpin,
+    {
+        Map::try_map(self, g)
+    }
+}
+
+/// A node in the tree of variants of a conditional query.
+///
+/// The macro arranges the variants of a query as the leaves of a balanced binary tree of
+/// branches, so selecting a variant only takes a handful of matches even for queries with many
+/// variants.
+#[derive(Clone, Copy, Debug)]
+pub enum Branch<L, R> {
+    Left(L),
+    Right(R),
+}
+
+impl<'q, DB, L, R> ConditionalQuery<'q, DB> for Branch<L, R>
+where
+    DB: Database,
+    L: ConditionalQuery<'q, DB>,
+    R: ConditionalQuery<'q, DB, Output = L::Output>,
+{
+    type Output = L::Output;
+
+    type Stream<'e>
+        = Branch<L::Stream<'e>, R::Stream<'e>>
+    where
+        'q: 'e,
+        Self: 'e,
+        Self::Output: 'e;
+
+    fn fetch<'e, 'c: 'e, E>(self, executor: E) -> BoxStream<'e, Result<Self::Output, Error>>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        match self {
+            Self::Left(left) => left.fetch(executor),
+            Self::Right(right) => right.fetch(executor),
+        }
+    }
+
+    fn fetch_unboxed<'e, 'c: 'e, E>(self, executor: E) -> Self::Stream<'e>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        match self {
+            Self::Left(left) => Branch::Left(left.fetch_unboxed(executor)),
+            Self::Right(right) => Branch::Right(right.fetch_unboxed(executor)),
+        }
+    }
+
+    fn fetch_many<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> BoxStream<'e, Result<Either<DB::QueryResult, Self::Output>, Error>>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        #[allow(deprecated)]
+        match self {
+            Self::Left(left) => left.fetch_many(executor),
+            Self::Right(right) => right.fetch_many(executor),
+        }
+    }
+
+    async fn fetch_all<'e, 'c: 'e, E>(self, executor: E) -> Result<Vec<Self::Output>, Error>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        match self {
+            Self::Left(left) => left.fetch_all(executor).await,
+            Self::Right(right) => right.fetch_all(executor).await,
+        }
+    }
+
+    async fn fetch_one<'e, 'c: 'e, E>(self, executor: E) -> Result<Self::Output, Error>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        match self {
+            Self::Left(left) => left.fetch_one(executor).await,
+            Self::Right(right) => right.fetch_one(executor).await,
+        }
+    }
+
+    async fn fetch_optional<'e, 'c: 'e, E>(self, executor: E) -> Result<Option<Self::Output>, Error>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        match self {
+            Self::Left(left) => left.fetch_optional(executor).await,
+            Self::Right(right) => right.fetch_optional(executor).await,
+        }
+    }
+
+    fn try_map<G, P>(self, g: G) -> impl ConditionalQuery<'q, DB, Output = P>
+    where
+        G: FnMut(Self::Output) -> Result<P, Error> + Send,
+        P: Send + Unpin,
+    {
+        match self {
+            Self::Left(left) => Branch::Left(left.try_map(g)),
+            Self::Right(right) => Branch::Right(right.try_map(g)),
+        }
+    }
+}
+
+/// A branch is itself a stream if both of its sides are, which is what the unboxed streams of
+/// conditional queries are built from.
+///
+/// The sides are required to be `Unpin`, as the streams of all variants are.
+impl<L, R> Stream for Branch<L, R>
+where
+    L: Stream + Unpin,
+    R: Stream<Item = L::Item> + Unpin,
+{
+    type Item = L::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut() {
+            Branch::Left(left) => Pin::new(left).poll_next(cx),
+            Branch::Right(right) => Pin::new(right).poll_next(cx),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Branch::Left(left) => left.size_hint(),
+            Branch::Right(right) => right.size_hint(),
+        }
+    }
+}
+
+impl<'q, DB, L, R> Execute<'q, DB> for Branch<L, R>
+where
+    DB: Database,
+    L: Execute<'q, DB>,
+    R: Execute<'q, DB>,
+{
+    fn sql(&self) -> &'q str {
+        match self {
+            Branch::Left(left) => left.sql(),
+            Branch::Right(right) => right.sql(),
+        }
+    }
+
+    fn statement(&self) -> Option<&DB::Statement<'q>> {
+        match self {
+            Branch::Left(left) => left.statement(),
+            Branch::Right(right) => right.statement(),
+        }
+    }
+
+    fn take_arguments(&mut self) -> Result<Option<DB::Arguments<'q>>, BoxDynError> {
+        match self {
+            Branch::Left(left) => left.take_arguments(),
+            Branch::Right(right) => right.take_arguments(),
+        }
+    }
+
+    fn persistent(&self) -> bool {
+        match self {
+            Branch::Left(left) => left.persistent(),
+            Branch::Right(right) => right.persistent(),
+        }
+    }
+}
+
+/// A conditional query along with its companion queries, generated by the macro's `count` option.
+///
+/// The companion queries apply the same bindings as the main query, but count the rows it
+/// returns or check whether it returns any.  See [`ConditionalMap::count`] and
+/// [`ConditionalMap::exists`].
+///
+/// They are built whenever they're run, so they can be run any number of times.
+#[derive(Debug)]
+pub struct Companions<Q, C, X> {
+    query: Q,
+    count: C,
+    exists: X,
+}
+
+impl<Q, C, X> Companions<Q, C, X> {
+    #[doc(hidden)]
+    pub fn new(query: Q, count: C, exists: X) -> Self {
+        Self {
+            query,
+            count,
+            exists,
+        }
+    }
+}
+
+/// Builds a companion query of the selected variant.
+///
+/// This is implemented for the closures generated by the macro's `count` option, and for
+/// [`Branch`], which selects between two of them.
+#[doc(hidden)]
+pub trait BuildQuery {
+    type Query;
+
+    fn build(&self) -> Self::Query;
+}
+
+impl<F, Q> BuildQuery for F
+where
+    F: Fn() -> Q,
+{
+    type Query = Q;
+
+    fn build(&self) -> Q {
+        self()
+    }
+}
+
+impl<L: BuildQuery, R: BuildQuery> BuildQuery for Branch<L, R> {
+    type Query = Branch<L::Query, R::Query>;
+
+    fn build(&self) -> Self::Query {
+        match self {
+            Branch::Left(left) => Branch::Left(left.build()),
+            Branch::Right(right) => Branch::Right(right.build()),
+        }
+    }
+}
+
+impl<'q, DB, Q, C, X> ConditionalQuery<'q, DB> for Companions<Q, C, X>
+where
+    DB: Database,
+    Q: ConditionalQuery<'q, DB>,
+    C: Send,
+    X: Send,
+{
+    type Output = Q::Output;
+
+    type Stream<'e>
+        = Q::Stream<'e>
+    where
+        'q: 'e,
+        Self: 'e,
+        Self::Output: 'e;
+
+    fn fetch<'e, 'c: 'e, E>(self, executor: E) -> BoxStream<'e, Result<Self::Output, Error>>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        self.query.fetch(executor)
+    }
+
+    fn fetch_unboxed<'e, 'c: 'e, E>(self, executor: E) -> Self::Stream<'e>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        self.query.fetch_unboxed(executor)
+    }
+
+    fn fetch_many<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> BoxStream<'e, Result<Either<DB::QueryResult, Self::Output>, Error>>
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        #[allow(deprecated)]
+        self.query.fetch_many(executor)
+    }
+
+    fn fetch_all<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Vec<Self::Output>, Error>> + Send + 'e
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        self.query.fetch_all(executor)
+    }
+
+    fn fetch_one<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Self::Output, Error>> + Send + 'e
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        self.query.fetch_one(executor)
+    }
+
+    fn fetch_optional<'e, 'c: 'e, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Option<Self::Output>, Error>> + Send + 'e
+    where
+        'q: 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        DB: 'e,
+        Self: 'e,
+        Self::Output: 'e,
+    {
+        self.query.fetch_optional(executor)
+    }
+
+    /// Mapping the rows of the main query drops the companion queries.
+    fn try_map<G, P>(self, g: G) -> impl ConditionalQuery<'q, DB, Output = P>
+    where
+        G: FnMut(Self::Output) -> Result<P, Error> + Send,
+        P: Send + Unpin,
+    {
+        self.query.try_map(g)
+    }
+}
+
+impl<'q, DB, Q, C, X> Execute<'q, DB> for Companions<Q, C, X>
+where
+    DB: Database,
+    Q: Execute<'q, DB>,
+    C: Send,
+    X: Send,
+{
+    fn sql(&self) -> &'q str {
+        self.query.sql()
+    }
+
+    fn statement(&self) -> Option<&DB::Statement<'q>> {
+        self.query.statement()
+    }
+
+    fn take_arguments(&mut self) -> Result<Option<DB::Arguments<'q>>, BoxDynError> {
+        self.query.take_arguments()
+    }
+
+    fn persistent(&self) -> bool {
+        self.query.persistent()
+    }
+}
+
+/// Static information about all variants of a single macro invocation.
+///
+/// This is implemented by a type emitted alongside every invocation of the macro.
+pub trait Variants {
+    /// The fully expanded SQL of every variant, in the order the variants were generated in.
+    const VARIANTS: &'static [&'static str];
+
+    /// The identifier of every variant, made up of its label and a hash of its SQL.
+    const IDS: &'static [&'static str];
+
+    /// The label of every variant, made up of the labels of the arms it was generated from.
+    const LABELS: &'static [&'static str];
+
+    /// The number of arguments bound at run-time by every variant.
+    const ARGUMENTS: &'static [usize];
+
+    /// The database every variant was generated for, as named by `sqlx::Database::NAME`, for
+    /// invocations passing `database = any`.  Empty for all other invocations, whose variants are
+    /// all generated for the same database.
+    const DATABASES: &'static [&'static str] = &[];
+
+    /// The location of the macro invocation.
+    const LOCATION: SourceLocation;
+}
+
+/// A macro invocation, along with all of its variants.
+///
+/// With the `call-sites` feature, every invocation registers its call site when the program
+/// starts, so it's known even if it's never executed.  See `call_sites`.
+#[derive(Clone, Copy, Debug)]
+pub struct CallSite {
+    /// The location of the macro invocation.
+    pub location: SourceLocation,
+    /// The fully expanded SQL of every variant, in the order the variants were generated in.
+    pub variants: &'static [&'static str],
+    /// The identifier of every variant, see [`ConditionalMap::variant_id`].
+    pub ids: &'static [&'static str],
+    /// The label of every variant, see [`ConditionalMap::variant_label`].
+    pub labels: &'static [&'static str],
+    /// The database every variant was generated for, see [`Variants::DATABASES`].
+    pub databases: &'static [&'static str],
+}
+
+impl CallSite {
+    /// Returns the call site described by `V`.
+    pub const fn of<V: Variants>() -> Self {
+        Self {
+            location: V::LOCATION,
+            variants: V::VARIANTS,
+            ids: V::IDS,
+            labels: V::LABELS,
+            databases: V::DATABASES,
+        }
+    }
+
+    /// Returns whether the variant at `index` can run on `database`, as named by
+    /// `sqlx::Database::NAME`.
+    ///
+    /// This is only false for variants of invocations passing `database = any` which were
+    /// generated for another database.
+    pub fn runs_on(&self, variant: usize, database: &str) -> bool {
+        self.databases
+            .get(variant)
+            .is_none_or(|&variant_database| variant_database == database)
+    }
+
+    /// Prepares the SQL of every variant on a connection acquired from `executor`, e.g. to warm up
+    /// its statement cache or to check that all variants are valid against the current schema.
+    ///
+    /// `executor` can be anything a connection can be acquired from, such as a pool, a
+    /// connection or a transaction.  Every variant is prepared, even if preparing an earlier one
+    /// failed.  Note that on PostgreSQL a failure aborts the surrounding transaction, if any.
+    /// Variants written for another database than the executor's are skipped, see
+    /// [`runs_on`](Self::runs_on).  Use `prepare_all_any` for executors of `sqlx::Any`.
+    ///
+    /// The call site of a single query is returned by [`ConditionalMap::call_site`].  No bindings
+    /// need to be in scope though, so with the `call-sites` feature all call sites of a program
+    /// can be checked at once:
+    ///
+    /// ```rust,ignore
+    /// let pool = SqlitePool::connect(&database_url).await?;
+    /// for site in call_sites() {
+    ///     site.prepare_all(&pool).await?;
+    /// }
+    /// ```
+    pub async fn prepare_all<'c, A>(&self, executor: A) -> Result<(), PrepareError>
+    where
+        A: Acquire<'c>,
+        for<'e> &'e mut <A::Database as Database>::Connection: Executor<'e, Database = A::Database>,
+    {
+        let mut connection = executor.acquire().await.map_err(PrepareError::Acquire)?;
+        self.prepare_variants::<A::Database>(&mut connection, <A::Database as Database>::NAME)
+            .await
+    }
+
+    /// Prepares the SQL of every variant which runs on `database` on `connection`.
+    pub(crate) async fn prepare_variants<DB>(
+        &self,
+        connection: &mut DB::Connection,
+        database: &str,
+    ) -> Result<(), PrepareError>
+    where
+        DB: Database,
+        for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    {
+        let mut failures = Vec::new();
+        for (variant, sql) in self.variants.iter().enumerate() {
+            if !self.runs_on(variant, database) {
+                continue;
+            }
+            if let Err(error) = (&mut *connection).prepare(sql).await {
+                failures.push(PrepareFailure {
+                    variant_id: self.ids[variant],
+                    variant_label: self.labels[variant],
+                    sql,
+                    error,
+                });
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(PrepareError::Failed(failures))
+        }
+    }
+}
+
+#[cfg(feature = "call-sites")]
+inventory::collect!(CallSite);
+
+/// Returns the call sites of all macro invocations in the program, in no particular order.
+///
+/// This requires the `call-sites` feature, which is enabled by the `coverage` feature.
+#[cfg(feature = "call-sites")]
+pub fn call_sites() -> impl Iterator<Item = &'static CallSite> {
+    inventory::iter::<CallSite>.into_iter()
+}
+
+#[cfg(not(feature = "tracing"))]
+type VariantStreamInner<S> = ObservedStream<S>;
+#[cfg(feature = "tracing")]
+type VariantStreamInner<S> = crate::instrument::InstrumentedStream<ObservedStream<S>>;
+
+pin_project! {
+    /// The stream returned by [`ConditionalMap::fetch_unboxed`].
+    ///
+    /// With the `tracing` feature enabled, the stream is polled inside the span of the query.
+    pub struct VariantStream<S> {
+        #[pin]
+        inner: VariantStreamInner<S>,
+    }
+}
+
+impl<S, T> Stream for VariantStream<S>
+where
+    S: Stream<Item = Result<T, Error>>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().inner.poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// The type generated by [`conditional_query_as!`].
+///
+/// It exposes the same methods as `sqlx::query::Map`, dispatching to whichever variant was
+/// selected when the macro's `match` was evaluated.
+///
+/// [`conditional_query_as!`]: crate::conditional_query_as
+pub struct ConditionalMap<Q, V> {
+    inner: Q,
+    variant: usize,
+    observer: Option<Arc<dyn QueryObserver>>,
+    #[cfg(feature = "capture-arguments")]
+    arguments: Vec<String>,
+    variants: PhantomData<fn() -> V>,
+}
+
+impl<Q, V: Variants> ConditionalMap<Q, V> {
+    /// The fully expanded SQL of every variant this invocation can select between.
+    pub const VARIANTS: &'static [&'static str] = V::VARIANTS;
+
+    #[doc(hidden)]
+    pub fn new(inner: Q, variant: usize) -> Self {
+        Self {
+            inner,
+            variant,
+            observer: None,
+            #[cfg(feature = "capture-arguments")]
+            arguments: Vec::new(),
+            variants: PhantomData,
+        }
+    }
+
+    #[doc(hidden)]
+    #[cfg(feature = "capture-arguments")]
+    pub fn with_arguments(mut self, arguments: Vec<String>) -> Self {
+        self.arguments = arguments;
+        self
+    }
+
+    /// Returns the `Debug` renderings of the run-time bound arguments of the selected variant, in
+    /// the order of their placeholders.
+    ///
+    /// Bindings referenced as `{name:redact}` are rendered as `<redacted>`.
+    #[cfg(feature = "capture-arguments")]
+    pub fn arguments(&self) -> &[String] {
+        &self.arguments
+    }
+
+    /// Notifies `observer` about the execution of this query, in addition to the global observer.
+    ///
+    /// See the [`observer`] module.
+    pub fn observe(mut self, observer: Arc<dyn QueryObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Prepares observing a fetch using the given method, see [`PendingObservation`].
+    fn observation(&self, method: &'static str) -> PendingObservation {
+        let pending = PendingObservation::new(
+            QueryInfo {
+                location: V::LOCATION,
+                method,
+                variant_id: self.variant_id(),
+                variant_label: self.variant_label(),
+                sql: self.sql(),
+            },
+            self.observer.clone(),
+        );
+
+        #[cfg(feature = "coverage")]
+        let pending = pending.record_coverage(crate::coverage::record::<V>, self.variant);
+
+        pending
+    }
+
+    /// Returns the call site of the macro invocation this query was generated by.
+    pub fn call_site(&self) -> &'static CallSite {
+        const { &CallSite::of::<V>() }
+    }
+
+    /// Prepares the SQL of every variant of this query on a connection acquired from `executor`.
+    ///
+    /// See [`CallSite::prepare_all`].
+    pub fn prepare_all<'c, A>(
+        &self,
+        executor: A,
+    ) -> impl Future<Output = Result<(), PrepareError>> + use<'c, A, Q, V>
+    where
+        A: Acquire<'c>,
+        for<'e> &'e mut <A::Database as Database>::Connection: Executor<'e, Database = A::Database>,
+    {
+        self.call_site().prepare_all(executor)
+    }
+
+    /// Returns the fully expanded SQL of the selected variant.
+    pub fn sql(&self) -> &'static str {
+        V::VARIANTS[self.variant]
+    }
+
+    /// Returns a stable identifier for the selected variant.
+    ///
+    /// The identifier is made up of the [label](Self::variant_label) of the variant and a hash of
+    /// its SQL, so unlike the position of the variant it doesn't change when unrelated arms are
+    /// added or removed.
+    pub fn variant_id(&self) -> &'static str {
+        V::IDS[self.variant]
+    }
+
+    /// Returns the label of the selected variant.
+    ///
+    /// This is the comma-separated labels of the match arms the variant was generated from, taken
+    /// from their `#[label = "..."]` attributes, or from their patterns for unlabelled arms.
+    pub fn variant_label(&self) -> &'static str {
+        V::LABELS[self.variant]
+    }
+
+    /// Creates the span a fetch using the given method runs in.
+    #[cfg(feature = "tracing")]
+    fn span(&self, method: &'static str) -> tracing::Span {
+        let span = crate::instrument::span(
+            method,
+            self.variant_label(),
+            self.sql(),
+            V::ARGUMENTS[self.variant],
+        );
+        #[cfg(feature = "capture-arguments")]
+        span.record("bindings", tracing::field::debug(&self.arguments));
+        span
+    }
+
+    /// Returns the fully expanded SQL of every variant this invocation can select between.
+    ///
+    /// This is the same as [`Self::VARIANTS`], which is hard to reach since the generated type
+    /// can't be named.
+    pub fn variants(&self) -> &'static [&'static str] {
+        V::VARIANTS
+    }
+
+    /// Map each row in the result to another type.
+    ///
+    /// See [`try_map`](Self::try_map) for a fallible version of this method.
+    ///
+    /// See [`sqlx::query::Map::map`](Map::map)
+    pub fn map<'q, DB, O, G, P>(
+        self,
+        mut g: G,
+    ) -> ConditionalMap<impl ConditionalQuery<'q, DB, Output = P>, V>
+    where
+        DB: Database,
+        Q: ConditionalQuery<'q, DB, Output = O>,
+        G: FnMut(O) -> P + Send,
+        P: Send + Unpin,
+    {
+        self.try_map(move |row| Ok(g(row)))
+    }
+
+    /// Map each row in the result to another type.
+    ///
+    /// See [`sqlx::query::Map::try_map`](Map::try_map)
+    pub fn try_map<'q, DB, O, G, P>(
+        self,
+        g: G,
+    ) -> ConditionalMap<impl ConditionalQuery<'q, DB, Output = P>, V>
+    where
+        DB: Database,
+        Q: ConditionalQuery<'q, DB, Output = O>,
+        G: FnMut(O) -> Result<P, Error> + Send,
+        P: Send + Unpin,
+    {
+        ConditionalMap {
+            inner: self.inner.try_map(g),
+            variant: self.variant,
+            observer: self.observer,
+            #[cfg(feature = "capture-arguments")]
+            arguments: self.arguments,
+            variants: PhantomData,
+        }
+    }
+
+    /// See [`sqlx::query::Map::fetch`](Map::fetch)
+    pub fn fetch<'q, 'e, 'c: 'e, DB, O, E>(self, executor: E) -> BoxStream<'e, Result<O, Error>>
+    where
+        'q: 'e,
+        DB: Database + 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        O: 'e,
+        Q: ConditionalQuery<'q, DB, Output = O> + 'e,
+    {
+        #[cfg(feature = "tracing")]
+        let span = self.span("fetch");
+        let observation = self.observation("fetch");
+
+        let stream = ObservedStream::new(self.inner.fetch(executor), observation);
+
+        #[cfg(feature = "tracing")]
+        let stream = crate::instrument::InstrumentedStream::new(stream, span);
+
+        Box::pin(stream)
+    }
+
+    /// Like [`fetch`](Self::fetch), but returns a concrete stream type instead of a `BoxStream`.
+    ///
+    /// The returned stream dispatches to the stream of whichever variant was selected, which saves
+    /// the allocation and the dynamic dispatch of boxing it.  The stream of every variant is still
+    /// boxed by SQLx itself though, so this only removes one of the two layers of boxing.  The
+    /// returned stream is always `Send` and `Unpin`, also after mapping the rows using
+    /// [`map`](Self::map) or [`try_map`](Self::try_map).
+    pub fn fetch_unboxed<'q, 'e, 'c: 'e, DB, O, E>(
+        self,
+        executor: E,
+    ) -> VariantStream<<Q as ConditionalQuery<'q, DB>>::Stream<'e>>
+    where
+        'q: 'e,
+        DB: Database + 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        O: 'e,
+        Q: ConditionalQuery<'q, DB, Output = O> + 'e,
+    {
+        #[cfg(feature = "tracing")]
+        let span = self.span("fetch_unboxed");
+        let observation = self.observation("fetch_unboxed");
+
+        let stream = ObservedStream::new(self.inner.fetch_unboxed(executor), observation);
+
+        #[cfg(feature = "tracing")]
+        let stream = crate::instrument::InstrumentedStream::new(stream, span);
+
+        VariantStream { inner: stream }
+    }
+
+    /// See [`sqlx::query::Map::fetch_many`](Map::fetch_many)
+    #[deprecated = "Only the SQLite driver supports multiple statements in one prepared statement and that behavior is deprecated. Use `sqlx::raw_sql()` instead. See https://github.com/launchbadge/sqlx/issues/3108 for discussion."]
+    pub fn fetch_many<'q, 'e, 'c: 'e, DB, O, E>(
+        self,
+        executor: E,
+    ) -> BoxStream<'e, Result<Either<DB::QueryResult, O>, Error>>
+    where
+        'q: 'e,
+        DB: Database + 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        O: 'e,
+        Q: ConditionalQuery<'q, DB, Output = O> + 'e,
+    {
+        #[cfg(feature = "tracing")]
+        let span = self.span("fetch_many");
+        let observation = self.observation("fetch_many");
+
+        #[allow(deprecated)]
+        let stream = ObservedStream::new(self.inner.fetch_many(executor), observation);
+
+        #[cfg(feature = "tracing")]
+        let stream = crate::instrument::InstrumentedStream::new(stream, span);
+
+        Box::pin(stream)
+    }
+
+    /// See [`sqlx::query::Map::fetch_all`](Map::fetch_all)
+    pub fn fetch_all<'q, 'e, 'c: 'e, DB, O, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Vec<O>, Error>> + Send + use<'q, 'e, 'c, DB, O, E, Q, V>
+    where
+        'q: 'e,
+        DB: Database + 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        O: 'e,
+        Q: ConditionalQuery<'q, DB, Output = O> + 'e,
+    {
+        #[cfg(feature = "tracing")]
+        let span = self.span("fetch_all");
+        let observation = self.observation("fetch_all");
+
+        let future = observer::observe(observation, self.inner.fetch_all(executor), Vec::len);
+
+        #[cfg(feature = "tracing")]
+        let future = crate::instrument::rows(span, future);
+
+        future
+    }
+
+    /// See [`sqlx::query::Map::fetch_one`](Map::fetch_one)
+    pub fn fetch_one<'q, 'e, 'c: 'e, DB, O, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<O, Error>> + Send + use<'q, 'e, 'c, DB, O, E, Q, V>
+    where
+        'q: 'e,
+        DB: Database + 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        O: 'e,
+        Q: ConditionalQuery<'q, DB, Output = O> + 'e,
+    {
+        #[cfg(feature = "tracing")]
+        let span = self.span("fetch_one");
+        let observation = self.observation("fetch_one");
+
+        let future = observer::observe(observation, self.inner.fetch_one(executor), |_| 1);
+
+        #[cfg(feature = "tracing")]
+        let future = crate::instrument::result(span, future);
+
+        future
+    }
+
+    /// See [`sqlx::query::Map::fetch_optional`](Map::fetch_optional)
+    pub fn fetch_optional<'q, 'e, 'c: 'e, DB, O, E>(
+        self,
+        executor: E,
+    ) -> impl Future<Output = Result<Option<O>, Error>> + Send + use<'q, 'e, 'c, DB, O, E, Q, V>
+    where
+        'q: 'e,
+        DB: Database + 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        O: 'e,
+        Q: ConditionalQuery<'q, DB, Output = O> + 'e,
+    {
+        #[cfg(feature = "tracing")]
+        let span = self.span("fetch_optional");
+        let observation = self.observation("fetch_optional");
+
+        let future = observer::observe(observation, self.inner.fetch_optional(executor), |row| {
+            usize::from(row.is_some())
+        });
+
+        #[cfg(feature = "tracing")]
+        let future = crate::instrument::result(span, future);
+
+        future
+    }
+}
+
+/// The companion queries generated by the macro's `count` option.
+impl<Q, C, X, V: Variants> ConditionalMap<Companions<Q, C, X>, V> {
+    /// Counts the rows the selected variant would return.
+    ///
+    /// This runs the companion `COUNT(*)` query generated by the macro's `count` option, which
+    /// applies the same bindings as the main query, except for the ones listed in
+    /// `count(omit = [...])`.  It can be called any number of times, before the main query is
+    /// mapped or executed.
+    ///
+    /// The query is observed and traced like the fetch methods, with `count` as the method.  The
+    /// reported SQL is the one of the selected variant, not of its companion query.
+    pub fn count<'q, 'e, 'c: 'e, DB, E>(
+        &self,
+        executor: E,
+    ) -> impl Future<Output = Result<i64, Error>> + Send + use<'q, 'e, 'c, DB, E, Q, C, X, V>
+    where
+        'q: 'e,
+        DB: Database + 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        C: BuildQuery<Query: ConditionalQuery<'q, DB, Output = i64> + 'e>,
+    {
+        #[cfg(feature = "tracing")]
+        let span = self.span("count");
+        let observation = self.observation("count");
+
+        let count = self.inner.count.build().fetch_one(executor);
+        let future = observer::observe(observation, count, |_| 1);
+
+        #[cfg(feature = "tracing")]
+        let future = crate::instrument::result(span, future);
+
+        future
+    }
+
+    /// Returns whether the selected variant would return any rows.
+    ///
+    /// This runs the companion `EXISTS` query generated by the macro's `count` option, see
+    /// [`count`](Self::count).
+    pub fn exists<'q, 'e, 'c: 'e, DB, E>(
+        &self,
+        executor: E,
+    ) -> impl Future<Output = Result<bool, Error>> + Send + use<'q, 'e, 'c, DB, E, Q, C, X, V>
+    where
+        'q: 'e,
+        DB: Database + 'e,
+        E: 'e + Executor<'c, Database = DB>,
+        X: BuildQuery<Query: ConditionalQuery<'q, DB, Output = ()> + 'e>,
+    {
+        #[cfg(feature = "tracing")]
+        let span = self.span("exists");
+        let observation = self.observation("exists");
+
+        let row = self.inner.exists.build().fetch_optional(executor);
+        let future = observer::observe(observation, row, |row| usize::from(row.is_some()));
+
+        #[cfg(feature = "tracing")]
+        let future = crate::instrument::result(span, future);
+
+        async move { Ok(future.await?.is_some()) }
+    }
+}
+
+/// Allows passing the selected variant directly to an [`Executor`], e.g. to
+/// `Executor::fetch_all` or to generic code written against [`Execute`].
+///
+/// Queries executed this way bypass the fetch methods of [`ConditionalMap`], so they aren't
+/// reported to [observers](crate::observer), don't run inside a `tracing` span, and aren't counted
+/// by the `coverage` feature.
+impl<'q, DB, Q, V> Execute<'q, DB> for ConditionalMap<Q, V>
+where
+    DB: Database,
+    Q: Execute<'q, DB>,
+{
+    fn sql(&self) -> &'q str {
+        self.inner.sql()
+    }
+
+    fn statement(&self) -> Option<&DB::Statement<'q>> {
+        self.inner.statement()
+    }
+
+    fn take_arguments(&mut self) -> Result<Option<DB::Arguments<'q>>, BoxDynError> {
+        self.inner.take_arguments()
+    }
+
+    fn persistent(&self) -> bool {
+        self.inner.persistent()
+    }
+}
+
+/// The error returned by [`CallSite::prepare_all`].
+#[derive(Debug)]
+pub enum PrepareError {
+    /// No connection could be acquired from the executor.
+    Acquire(Error),
+    /// The variants which failed to be prepared, in the order of their declaration.
+    Failed(Vec<PrepareFailure>),
+}
+
+/// A variant which failed to be prepared.
+#[derive(Debug)]
+pub struct PrepareFailure {
+    pub variant_id: &'static str,
+    pub variant_label: &'static str,
+    pub sql: &'static str,
+    pub error: Error,
+}
+
+impl fmt::Display for PrepareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Acquire(_) => write!(f, "failed to acquire a connection to prepare variants on"),
+            Self::Failed(failures) => {
+                write!(f, "failed to prepare {} variants", failures.len())?;
+                for failure in failures {
+                    write!(f, "\n  {}: {}", failure.variant_id, failure.error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PrepareError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Acquire(error) => Some(error),
+            Self::Failed(_) => None,
+        }
+    }
+}
+
+impl<Q, V: Variants> fmt::Debug for ConditionalMap<Q, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("ConditionalMap");
+        f.field("variant_id", &self.variant_id())
+            .field("sql", &self.sql());
+        #[cfg(feature = "capture-arguments")]
+        f.field("arguments", &self.arguments);
+        f.finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{
+        sqlite::{SqliteArguments, SqliteRow},
+        Connection, Row, Sqlite, SqliteConnection,
+    };
+
+    use super::*;
+
+    struct TestVariants;
+
+    impl Variants for TestVariants {
+        const VARIANTS: &'static [&'static str] = &[
+            "SELECT id FROM users",
+            "SELECT missing FROM users",
+            "SELECT id FROM users WHERE id = ?",
+        ];
+        const IDS: &'static [&'static str] = &["a#00000001", "b#00000002", "c#00000003"];
+        const LABELS: &'static [&'static str] = &["a", "b", "c"];
+        const ARGUMENTS: &'static [usize] = &[0, 0, 1];
+        const LOCATION: SourceLocation = SourceLocation {
+            file: "src/map.rs",
+            line: 1,
+            column: 1,
+        };
+    }
+
+    type RowMapper = fn(SqliteRow) -> Result<i64, Error>;
+    type TestQuery =
+        ConditionalMap<Map<'static, Sqlite, RowMapper, SqliteArguments<'static>>, TestVariants>;
+
+    /// Returns a connection to an in-memory database holding the users 1, 2 and 3.
+    async fn connection() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::raw_sql(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY); INSERT INTO users VALUES (1), (2), (3);",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        conn
+    }
+
+    /// Returns the query running the given variant of `TestVariants`, binding 2 to its parameter.
+    fn query(variant: usize) -> TestQuery {
+        let mut query = sqlx::query::<Sqlite>(TestVariants::VARIANTS[variant]);
+        if TestVariants::ARGUMENTS[variant] > 0 {
+            query = query.bind(2_i64);
+        }
+        ConditionalMap::new(
+            query.try_map((|row: SqliteRow| row.try_get(0)) as RowMapper),
+            variant,
+        )
+    }
+
+    fn assert_send_unpin<T: Send + Unpin>(_: &T) {}
+
+    #[tokio::test]
+    async fn unboxed_streams_stay_unpin_after_mapping() {
+        let mut conn = connection().await;
+
+        let mut stream = query(0).map(|id| id * 10).fetch_unboxed(&mut conn);
+        assert_send_unpin(&stream);
+
+        let mut ids = Vec::new();
+        while let Some(id) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            ids.push(id.unwrap());
+        }
+        assert_eq!(ids, [10, 20, 30]);
+    }
+
+    #[tokio::test]
+    async fn maps_rows_of_the_selected_variant() {
+        let mut conn = connection().await;
+
+        let ids = query(0)
+            .map(|id| id * 10)
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(ids, [10, 20, 30]);
+
+        let query = query(2).try_map(|id| Ok(format!("user {id}")));
+        assert_eq!(query.variant_label(), "c");
+        assert_eq!(query.fetch_one(&mut conn).await.unwrap(), "user 2");
+
+        let error = self::query(0)
+            .map(|id| id + 1)
+            .try_map(|id| match id {
+                4 => Err(Error::RowNotFound),
+                id => Ok(id),
+            })
+            .fetch_all(&mut conn)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::RowNotFound), "{error}");
+    }
+
+    #[test]
+    fn exposes_the_sql_of_the_variants() {
+        let query = query(2);
+
+        assert_eq!(query.sql(), "SELECT id FROM users WHERE id = ?");
+        assert_eq!(query.variant_id(), "c#00000003");
+        assert_eq!(query.variants(), TestVariants::VARIANTS);
+        assert_eq!(TestQuery::VARIANTS, TestVariants::VARIANTS);
+
+        let debug = format!("{query:?}");
+        assert!(
+            debug.starts_with(
+                r#"ConditionalMap { variant_id: "c#00000003", sql: "SELECT id FROM users WHERE id = ?""#
+            ),
+            "{debug}"
+        );
+    }
+
+    #[tokio::test]
+    async fn executes_the_selected_variant_through_an_executor() {
+        let mut conn = connection().await;
+
+        let query = query(2);
+        assert_eq!(Execute::sql(&query), "SELECT id FROM users WHERE id = ?");
+
+        let rows = (&mut conn).fetch_all(query).await.unwrap();
+        let ids: Vec<i64> = rows.iter().map(|row| row.get(0)).collect();
+        assert_eq!(ids, [2]);
+    }
+
+    #[tokio::test]
+    async fn reports_executed_variants_to_observers() {
+        let mut conn = connection().await;
+        let observer = Arc::new(observer::RecordingObserver::default());
+
+        query(0)
+            .observe(observer.clone())
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        query(2)
+            .observe(observer.clone())
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        query(1)
+            .observe(observer.clone())
+            .fetch_optional(&mut conn)
+            .await
+            .unwrap_err();
+
+        // Dropping a stream before it's exhausted cancels the query.
+        let mut stream = query(0).observe(observer.clone()).fetch(&mut conn);
+        std::future::poll_fn(|cx| stream.as_mut().poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        drop(stream);
+
+        assert_eq!(observer.variant_labels(), ["a", "c", "b", "a"]);
+        let queries = observer.queries();
+        let methods: Vec<_> = queries.iter().map(|query| query.info.method).collect();
+        assert_eq!(
+            methods,
+            ["fetch_all", "fetch_one", "fetch_optional", "fetch"]
+        );
+        assert_eq!(queries[0].info.location, TestVariants::LOCATION);
+        assert_eq!(queries[1].info.sql, "SELECT id FROM users WHERE id = ?");
+
+        let outcomes: Vec<_> = queries.into_iter().map(|query| query.outcome).collect();
+        assert!(
+            matches!(
+                outcomes.as_slice(),
+                [
+                    observer::RecordedOutcome::Success { rows: 3 },
+                    observer::RecordedOutcome::Success { rows: 1 },
+                    observer::RecordedOutcome::Error(_),
+                    observer::RecordedOutcome::Cancelled,
+                ]
+            ),
+            "{outcomes:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_report_queries_which_never_ran() {
+        let mut conn = connection().await;
+        let observer = Arc::new(observer::RecordingObserver::default());
+
+        drop(query(0).observe(observer.clone()).fetch_all(&mut conn));
+        drop(query(0).observe(observer.clone()).fetch(&mut conn));
+        drop(query(0).observe(observer.clone()).fetch_unboxed(&mut conn));
+
+        assert!(observer.queries().is_empty());
+    }
+
+    #[cfg(feature = "coverage")]
+    #[tokio::test]
+    async fn counts_variants_once_they_run() {
+        /// The variants of `TestVariants` at a call site no other test executes.
+        struct CoveredVariants;
+
+        impl Variants for CoveredVariants {
+            const VARIANTS: &'static [&'static str] = TestVariants::VARIANTS;
+            const IDS: &'static [&'static str] = TestVariants::IDS;
+            const LABELS: &'static [&'static str] = TestVariants::LABELS;
+            const ARGUMENTS: &'static [usize] = TestVariants::ARGUMENTS;
+            const LOCATION: SourceLocation = SourceLocation {
+                file: "src/map/coverage.rs",
+                line: 1,
+                column: 1,
+            };
+        }
+
+        let mut conn = connection().await;
+        let covered =
+            |variant| ConditionalMap::<_, CoveredVariants>::new(query(variant).inner, variant);
+
+        drop(covered(0).fetch_all(&mut conn));
+        drop(covered(0).fetch(&mut conn));
+        covered(2).fetch_all(&mut conn).await.unwrap();
+
+        let report = crate::coverage::report().file("src/map/coverage.rs");
+        let executions: Vec<_> = report.sites[0]
+            .variants
+            .iter()
+            .map(|variant| variant.executions)
+            .collect();
+        assert_eq!(executions, [0, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn runs_companion_queries_repeatedly() {
+        let mut conn = connection().await;
+        let observer = Arc::new(observer::RecordingObserver::default());
+        let id = 2_i64;
+
+        let query = ConditionalMap::<_, TestVariants>::new(
+            Companions::new(
+                query(2).inner,
+                || {
+                    sqlx::query::<Sqlite>(
+                        "SELECT COUNT(*) FROM (SELECT id FROM users WHERE id = ?)",
+                    )
+                    .bind(id)
+                    .try_map(|row: SqliteRow| row.try_get::<i64, _>(0))
+                },
+                || {
+                    sqlx::query::<Sqlite>(
+                        "SELECT 1 WHERE EXISTS (SELECT id FROM users WHERE id = ?)",
+                    )
+                    .bind(id)
+                    .try_map(|_| Ok(()))
+                },
+            ),
+            2,
+        )
+        .observe(observer.clone());
+
+        assert_eq!(query.count(&mut conn).await.unwrap(), 1);
+        assert!(query.exists(&mut conn).await.unwrap());
+        assert_eq!(query.count(&mut conn).await.unwrap(), 1);
+        assert_eq!(query.fetch_all(&mut conn).await.unwrap(), [2]);
+
+        let queries = observer.queries();
+        let methods: Vec<_> = queries.iter().map(|query| query.info.method).collect();
+        assert_eq!(methods, ["count", "exists", "count", "fetch_all"]);
+        assert_eq!(observer.variant_labels(), ["c", "c", "c", "c"]);
+    }
+
+    /// A subscriber recording the fields of all spans.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<std::sync::Mutex<Vec<(&'static str, String)>>>);
+
+    #[cfg(feature = "tracing")]
+    impl SpanRecorder {
+        /// Returns and forgets the recorded fields.
+        fn take(&self) -> std::collections::HashMap<&'static str, String> {
+            self.0.lock().unwrap().drain(..).collect()
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for &SpanRecorder {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.name(), format!("{value:?}")));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            span.record(&mut &*self);
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            values.record(&mut &*self);
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn records_the_executed_variant_in_spans() {
+        let mut conn = connection().await;
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        query(2).fetch_all(&mut conn).await.unwrap();
+        let fields = recorder.take();
+        assert_eq!(fields["method"], r#""fetch_all""#);
+        assert_eq!(fields["variant"], r#""c""#);
+        assert_eq!(fields["sql"], r#""SELECT id FROM users WHERE id = ?""#);
+        assert_eq!(fields["arguments"], "1");
+        assert_eq!(fields["rows"], "1");
+        assert!(!fields.contains_key("error"));
+
+        query(1).fetch_optional(&mut conn).await.unwrap_err();
+        let fields = recorder.take();
+        assert_eq!(fields["method"], r#""fetch_optional""#);
+        assert_eq!(fields["variant"], r#""b""#);
+        assert!(fields["error"].contains("no such column"), "{fields:?}");
+    }
+
+    #[tokio::test]
+    async fn prepare_all_reports_failed_variants() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE users (id INTEGER PRIMARY KEY)")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let error = query(0).prepare_all(&mut conn).await.unwrap_err();
+
+        let PrepareError::Failed(failures) = error else {
+            panic!("{error}");
+        };
+        let failed: Vec<_> = failures.iter().map(|failure| failure.variant_id).collect();
+        assert_eq!(failed, ["b#00000002"]);
+    }
+}
@@ -0,0 +1,407 @@
+//! Hooks for observing the execution of conditional queries, e.g. to export metrics.
+//!
+//! Observers can be registered globally using [`set_global_observer`], or for a single query
+//! using [`ConditionalMap::observe`].  Both are notified whenever one of the fetch methods of a
+//! query starts and finishes.  A query starts when its future or stream is first polled, so
+//! queries which are dropped before that aren't reported.
+//!
+//! [`ConditionalMap::observe`]: crate::ConditionalMap::observe
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+use sqlx_core::Error;
+
+static GLOBAL_OBSERVER: RwLock<Option<Arc<dyn QueryObserver>>> = RwLock::new(None);
+
+/// Registers an observer which is notified about every executed conditional query.
+///
+/// This replaces any previously registered global observer.
+pub fn set_global_observer(observer: Arc<dyn QueryObserver>) {
+    *GLOBAL_OBSERVER
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = Some(observer);
+}
+
+/// Removes the global observer, if any.
+pub fn clear_global_observer() {
+    *GLOBAL_OBSERVER
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = None;
+}
+
+fn global_observer() -> Option<Arc<dyn QueryObserver>> {
+    GLOBAL_OBSERVER
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+/// Receives notifications about executed conditional queries.
+pub trait QueryObserver: Send + Sync {
+    /// Called when a fetch method of a query is invoked.
+    fn on_start(&self, query: &QueryInfo) {
+        let _ = query;
+    }
+
+    /// Called when a fetch method of a query finished, failed or was cancelled.
+    fn on_finish(&self, query: &QueryInfo, duration: Duration, outcome: QueryOutcome<'_>);
+}
+
+/// The location of a macro invocation in the source code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceLocation {
+    pub file: &'static str,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Describes the query an observer is notified about.
+#[derive(Clone, Copy, Debug)]
+pub struct QueryInfo {
+    /// The location of the macro invocation the query was generated by.
+    pub location: SourceLocation,
+    /// The name of the fetch method that was called, e.g. `fetch_all`.
+    pub method: &'static str,
+    /// The identifier of the executed variant.
+    pub variant_id: &'static str,
+    /// The label of the executed variant.
+    pub variant_label: &'static str,
+    /// The fully expanded SQL of the executed variant.
+    pub sql: &'static str,
+}
+
+/// How the execution of a query ended.
+#[derive(Debug)]
+pub enum QueryOutcome<'a> {
+    /// The query succeeded, returning the given number of rows.
+    Success { rows: usize },
+    /// The query failed.
+    Error(&'a Error),
+    /// The future or stream of the query was dropped before it completed.
+    Cancelled,
+}
+
+/// An observer which records every finished query, meant to be used in tests.
+///
+/// ```rust,ignore
+/// let observer = Arc::new(RecordingObserver::default());
+/// conditional_query_as!(/* ... */)
+///     .observe(observer.clone())
+///     .fetch_all(&pool)
+///     .await?;
+/// assert_eq!(observer.variant_labels(), ["with_limit"]);
+/// ```
+#[derive(Debug, Default)]
+pub struct RecordingObserver {
+    queries: Mutex<Vec<RecordedQuery>>,
+}
+
+/// A query recorded by a [`RecordingObserver`].
+#[derive(Clone, Debug)]
+pub struct RecordedQuery {
+    pub info: QueryInfo,
+    pub duration: Duration,
+    pub outcome: RecordedOutcome,
+}
+
+/// An owned version of [`QueryOutcome`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordedOutcome {
+    Success { rows: usize },
+    Error(String),
+    Cancelled,
+}
+
+impl RecordingObserver {
+    /// Returns all recorded queries in the order they finished in.
+    pub fn queries(&self) -> Vec<RecordedQuery> {
+        self.lock().clone()
+    }
+
+    /// Returns the identifiers of the variants of all recorded queries.
+    pub fn variant_ids(&self) -> Vec<&'static str> {
+        self.lock()
+            .iter()
+            .map(|query| query.info.variant_id)
+            .collect()
+    }
+
+    /// Returns the labels of the variants of all recorded queries.
+    pub fn variant_labels(&self) -> Vec<&'static str> {
+        self.lock()
+            .iter()
+            .map(|query| query.info.variant_label)
+            .collect()
+    }
+
+    /// Removes all recorded queries.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<RecordedQuery>> {
+        self.queries.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl QueryObserver for RecordingObserver {
+    fn on_finish(&self, query: &QueryInfo, duration: Duration, outcome: QueryOutcome<'_>) {
+        let outcome = match outcome {
+            QueryOutcome::Success { rows } => RecordedOutcome::Success { rows },
+            QueryOutcome::Error(error) => RecordedOutcome::Error(error.to_string()),
+            QueryOutcome::Cancelled => RecordedOutcome::Cancelled,
+        };
+        self.lock().push(RecordedQuery {
+            info: *query,
+            duration,
+            outcome,
+        });
+    }
+}
+
+/// An execution of a query which hasn't started yet.
+///
+/// The observers are only notified once the future or stream of the query is first polled, so a
+/// query which is dropped before that isn't reported at all.
+pub(crate) struct PendingObservation {
+    info: QueryInfo,
+    observer: Option<Arc<dyn QueryObserver>>,
+    #[cfg(feature = "coverage")]
+    coverage: Option<(fn(usize), usize)>,
+}
+
+impl PendingObservation {
+    pub(crate) fn new(info: QueryInfo, observer: Option<Arc<dyn QueryObserver>>) -> Self {
+        Self {
+            info,
+            observer,
+            #[cfg(feature = "coverage")]
+            coverage: None,
+        }
+    }
+
+    /// Passes `variant` to `record` once the execution starts, see [`crate::coverage`].
+    #[cfg(feature = "coverage")]
+    pub(crate) fn record_coverage(mut self, record: fn(usize), variant: usize) -> Self {
+        self.coverage = Some((record, variant));
+        self
+    }
+
+    /// Starts observing the execution, see [`Observation::start`].
+    pub(crate) fn start(self) -> Option<Observation> {
+        #[cfg(feature = "coverage")]
+        if let Some((record, variant)) = self.coverage {
+            record(variant);
+        }
+
+        Observation::start(self.info, self.observer.as_ref())
+    }
+}
+
+/// A single observed execution of a query.
+///
+/// Dropping an observation which wasn't finished reports the query as cancelled.
+pub(crate) struct Observation {
+    observers: Vec<Arc<dyn QueryObserver>>,
+    info: QueryInfo,
+    start: Instant,
+    rows: usize,
+    finished: bool,
+}
+
+impl Observation {
+    /// Notifies the global observer and `observer` that the query started, returning `None` if
+    /// there's nobody to notify.
+    pub(crate) fn start(
+        info: QueryInfo,
+        observer: Option<&Arc<dyn QueryObserver>>,
+    ) -> Option<Self> {
+        let observers: Vec<_> = global_observer()
+            .into_iter()
+            .chain(observer.cloned())
+            .collect();
+        if observers.is_empty() {
+            return None;
+        }
+
+        for observer in &observers {
+            observer.on_start(&info);
+        }
+
+        Some(Self {
+            observers,
+            info,
+            start: Instant::now(),
+            rows: 0,
+            finished: false,
+        })
+    }
+
+    pub(crate) fn finish(mut self, outcome: QueryOutcome<'_>) {
+        self.notify(outcome);
+    }
+
+    fn notify(&mut self, outcome: QueryOutcome<'_>) {
+        self.finished = true;
+        let duration = self.start.elapsed();
+        for observer in &self.observers {
+            observer.on_finish(&self.info, duration, outcome_ref(&outcome));
+        }
+    }
+}
+
+impl Drop for Observation {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.notify(QueryOutcome::Cancelled);
+        }
+    }
+}
+
+fn outcome_ref<'a>(outcome: &QueryOutcome<'a>) -> QueryOutcome<'a> {
+    match outcome {
+        QueryOutcome::Success { rows } => QueryOutcome::Success { rows: *rows },
+        QueryOutcome::Error(error) => QueryOutcome::Error(error),
+        QueryOutcome::Cancelled => QueryOutcome::Cancelled,
+    }
+}
+
+/// Reports the outcome of `future` to `pending`, using `rows` to count the returned rows.
+///
+/// The observation starts when the returned future is first polled.
+pub(crate) async fn observe<T>(
+    pending: PendingObservation,
+    future: impl Future<Output = Result<T, Error>>,
+    rows: fn(&T) -> usize,
+) -> Result<T, Error> {
+    let observation = pending.start();
+    let result = future.await;
+    if let Some(observation) = observation {
+        match &result {
+            Ok(output) => observation.finish(QueryOutcome::Success { rows: rows(output) }),
+            Err(error) => observation.finish(QueryOutcome::Error(error)),
+        }
+    }
+    result
+}
+
+pin_project! {
+    /// A stream which reports its rows and outcome to an [`Observation`], starting it when the
+    /// stream is first polled.
+    pub(crate) struct ObservedStream<S> {
+        #[pin]
+        inner: S,
+        pending: Option<PendingObservation>,
+        observation: Option<Observation>,
+    }
+}
+
+impl<S> ObservedStream<S> {
+    pub(crate) fn new(inner: S, pending: PendingObservation) -> Self {
+        Self {
+            inner,
+            pending: Some(pending),
+            observation: None,
+        }
+    }
+}
+
+impl<S, T> Stream for ObservedStream<S>
+where
+    S: Stream<Item = Result<T, Error>>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if let Some(pending) = this.pending.take() {
+            *this.observation = pending.start();
+        }
+        let item = this.inner.poll_next(cx);
+
+        match &item {
+            Poll::Ready(Some(Ok(_))) => {
+                if let Some(observation) = this.observation {
+                    observation.rows += 1;
+                }
+            }
+            Poll::Ready(Some(Err(error))) => {
+                if let Some(observation) = this.observation.take() {
+                    observation.finish(QueryOutcome::Error(error));
+                }
+            }
+            Poll::Ready(None) => {
+                if let Some(observation) = this.observation.take() {
+                    let rows = observation.rows;
+                    observation.finish(QueryOutcome::Success { rows });
+                }
+            }
+            Poll::Pending => {}
+        }
+
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> QueryInfo {
+        QueryInfo {
+            location: SourceLocation {
+                file: "src/lib.rs",
+                line: 1,
+                column: 1,
+            },
+            method: "fetch_all",
+            variant_id: "with_limit#00000000",
+            variant_label: "with_limit",
+            sql: "SELECT 1 LIMIT $1",
+        }
+    }
+
+    #[test]
+    fn records_finished_and_cancelled_queries() {
+        let observer = Arc::new(RecordingObserver::default());
+        let per_call: Arc<dyn QueryObserver> = observer.clone();
+
+        Observation::start(info(), Some(&per_call))
+            .unwrap()
+            .finish(QueryOutcome::Success { rows: 3 });
+        drop(Observation::start(info(), Some(&per_call)));
+
+        let outcomes: Vec<_> = observer
+            .queries()
+            .into_iter()
+            .map(|query| query.outcome)
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                RecordedOutcome::Success { rows: 3 },
+                RecordedOutcome::Cancelled
+            ]
+        );
+        assert_eq!(observer.variant_labels(), ["with_limit", "with_limit"]);
+    }
+}
@@ -0,0 +1,62 @@
+#![cfg(all(feature = "any", feature = "sqlite"))]
+
+use sqlx::{any::AnyPoolOptions, AnyPool};
+use sqlx_conditional_queries::{conditional_query_as, map::PrepareError};
+
+#[derive(sqlx::FromRow)]
+struct User {
+    #[allow(dead_code)]
+    id: i64,
+}
+
+/// Returns a pool of a single in-memory database holding the users 1, 2 and 3.
+async fn pool() -> AnyPool {
+    sqlx::any::install_default_drivers();
+    let pool = AnyPoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::raw_sql(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY); INSERT INTO users VALUES (1), (2), (3);",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    pool
+}
+
+#[tokio::test]
+async fn prepares_every_variant_of_a_query() {
+    let pool = pool().await;
+    let archived = false;
+
+    let query = conditional_query_as!(
+        User,
+        "SELECT id FROM {#table}",
+        #table = match archived {
+            true => "archived_users",
+            false => "users",
+        },
+        database = any,
+    );
+
+    let call_site = query.call_site();
+    assert_eq!(call_site.location.file, file!());
+    assert!(call_site
+        .labels
+        .iter()
+        .all(|&label| label == "true" || label == "false"));
+
+    let error = query.prepare_all(&pool).await.unwrap_err();
+    let PrepareError::Failed(failures) = error else {
+        panic!("{error}");
+    };
+    let failed: Vec<_> = failures
+        .iter()
+        .map(|failure| failure.variant_label)
+        .collect();
+    assert_eq!(failed, ["true"]);
+
+    assert_eq!(query.fetch_all(&pool).await.unwrap().len(), 3);
+}